@@ -1,5 +1,7 @@
 use std::convert::TryFrom;
+use std::mem;
 
+use crate::diagnostic;
 use crate::error::{FormulaError, InvalidError};
 use crate::sequence::Sequence;
 use crate::term::Ski;
@@ -12,6 +14,21 @@ pub struct AstInner {
 }
 
 impl AstInner {
+    pub fn new(function: Ast, argument: Ast) -> Self {
+        AstInner {
+            function: Box::new(function),
+            argument: Box::new(argument),
+        }
+    }
+
+    pub fn function(&self) -> &Ast {
+        &self.function
+    }
+
+    pub fn argument(&self) -> &Ast {
+        &self.argument
+    }
+
     pub fn to_function(&self) -> Ast {
         *self.function.clone()
     }
@@ -21,11 +38,43 @@ impl AstInner {
     }
 
     pub fn into_function(self) -> Ast {
-        *self.function
+        self.into_parts().0
     }
 
     pub fn into_argument(self) -> Ast {
-        *self.argument
+        self.into_parts().1
+    }
+
+    pub fn into_parts(mut self) -> (Ast, Ast) {
+        // Drop を実装しているので取り出す代わりに差し替える
+        (
+            mem::replace(&mut *self.function, PLACEHOLDER),
+            mem::replace(&mut *self.argument, PLACEHOLDER),
+        )
+    }
+
+    // 子のうち適用であるものを rest に移す
+    fn take_applications(&mut self, rest: &mut Vec<Ast>) {
+        for child in [&mut self.function, &mut self.argument] {
+            if let Ast::Apply(_) = **child {
+                rest.push(mem::replace(&mut **child, PLACEHOLDER));
+            }
+        }
+    }
+}
+
+const PLACEHOLDER: Ast = Ast::Leaf(Atom::I);
+
+/// 深い木を再帰せずに破棄する
+impl Drop for AstInner {
+    fn drop(&mut self) {
+        let mut rest = Vec::new();
+        self.take_applications(&mut rest);
+        while let Some(mut ast) = rest.pop() {
+            if let Ast::Apply(inner) = &mut ast {
+                inner.take_applications(&mut rest);
+            }
+        }
     }
 }
 
@@ -36,67 +85,128 @@ pub enum Ast {
     Apply(AstInner),
 }
 
+impl Ast {
+    /// 葉 (原子コンビネータ) の個数
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        let mut rest = vec![self];
+        while let Some(ast) = rest.pop() {
+            match ast {
+                Ast::Leaf(_) => size += 1,
+                Ast::Apply(inner) => {
+                    rest.push(inner.function());
+                    rest.push(inner.argument());
+                }
+            }
+        }
+        size
+    }
+
+    /// 根から最も遠い葉までの適用の段数。原子コンビネータ1つならば0
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut rest = vec![(self, 0)];
+        while let Some((ast, d)) = rest.pop() {
+            match ast {
+                Ast::Leaf(_) => depth = depth.max(d),
+                Ast::Apply(inner) => {
+                    rest.push((inner.function(), d + 1));
+                    rest.push((inner.argument(), d + 1));
+                }
+            }
+        }
+        depth
+    }
+}
+
 impl From<Ski> for Ast {
     fn from(ski: Ski) -> Self {
-        match ski {
-            Ski::S => Ast::Leaf(Atom::S),
-            Ski::K => Ast::Leaf(Atom::K),
-            Ski::I => Ast::Leaf(Atom::I),
-            // Sp(x) = `sx
-            Ski::Sp(sp) => Ast::Apply(AstInner {
-                function: Box::new(Ast::Leaf(Atom::S)),
-                argument: Box::new(Ast::from(sp.get())),
-            }),
-            // Kp(x) = `kx
-            Ski::Kp(kp) => Ast::Apply(AstInner {
-                function: Box::new(Ast::Leaf(Atom::K)),
-                argument: Box::new(Ast::from(kp.get())),
-            }),
-            // Spp(x, y) = `sp(x)y = ``sxy = `(`sx)y
-            Ski::Spp(spp) => Ast::Apply(AstInner {
-                function: Box::new(Ast::Apply(AstInner {
-                    function: Box::new(Ast::Leaf(Atom::S)),
-                    argument: Box::new(Ast::from(spp.first())),
-                })),
-                argument: Box::new(Ast::from(spp.second())),
-            }),
+        enum Frame {
+            Visit(Ski),
+            Apply,
+        }
+
+        let mut rest = vec![Frame::Visit(ski)];
+        let mut done = Vec::new();
+        while let Some(frame) = rest.pop() {
+            match frame {
+                Frame::Visit(Ski::S) => done.push(Ast::Leaf(Atom::S)),
+                Frame::Visit(Ski::K) => done.push(Ast::Leaf(Atom::K)),
+                Frame::Visit(Ski::I) => done.push(Ast::Leaf(Atom::I)),
+                // Sp(x) = `sx
+                Frame::Visit(Ski::Sp(sp)) => {
+                    done.push(Ast::Leaf(Atom::S));
+                    rest.push(Frame::Apply);
+                    rest.push(Frame::Visit(sp.into_inner()));
+                }
+                // Kp(x) = `kx
+                Frame::Visit(Ski::Kp(kp)) => {
+                    done.push(Ast::Leaf(Atom::K));
+                    rest.push(Frame::Apply);
+                    rest.push(Frame::Visit(kp.into_inner()));
+                }
+                // Spp(x, y) = `sp(x)y = ``sxy = `(`sx)y
+                Frame::Visit(Ski::Spp(spp)) => {
+                    let (first, second) = spp.into_parts();
+                    done.push(Ast::Leaf(Atom::S));
+                    rest.push(Frame::Apply);
+                    rest.push(Frame::Visit(second));
+                    rest.push(Frame::Apply);
+                    rest.push(Frame::Visit(first));
+                }
+                Frame::Apply => {
+                    let argument = done.pop().unwrap();
+                    let function = done.pop().unwrap();
+                    done.push(Ast::Apply(AstInner::new(function, argument)));
+                }
+            }
         }
+        done.pop().unwrap()
     }
 }
 
 impl TryFrom<Sequence> for Ast {
     type Error = FormulaError;
 
-    fn try_from(mut seq: Sequence) -> Result<Self, Self::Error> {
+    /// 前置記法を末尾から読み、適用のたびにスタックの上2つを組み立てる
+    fn try_from(seq: Sequence) -> Result<Self, Self::Error> {
         seq.is_valid()?;
-        assert_ne!(seq.len(), 0);
-        match seq.dequeue().unwrap() {
-            Token::Atom(atom) => Ok(Ast::Leaf(atom)),
-            Token::Apply => {
-                let p = seq.valid_point()?;
-                let (function, argument) = seq.split(p + 1);
-                let function = Box::new(Ast::try_from(function)?);
-                let argument = Box::new(Ast::try_from(argument)?);
-                Ok(Ast::Apply(AstInner { function, argument }))
+        let mut done = Vec::new();
+        for token in seq.into_iter().rev() {
+            match token {
+                Token::Atom(atom) => done.push(Ast::Leaf(atom)),
+                Token::Apply => {
+                    let function = done.pop().unwrap();
+                    let argument = done.pop().unwrap();
+                    done.push(Ast::Apply(AstInner::new(function, argument)));
+                }
             }
         }
+        Ok(done.pop().unwrap())
     }
 }
 
 impl TryFrom<&str> for Ast {
     type Error = InvalidError;
 
+    /// Unlambda 記法の項を読む。エラーは入力の中の位置を持つ
     fn try_from(str: &str) -> Result<Self, Self::Error> {
-        match Sequence::try_from(str) {
-            Ok(seq) => match Ast::try_from(seq) {
-                Ok(ast) => Ok(ast),
-                Err(e) => Err(InvalidError::from(e)),
-            },
-            Err(e) => Err(InvalidError::from(e)),
-        }
+        parse(str, Sequence::try_from(str)?)
     }
 }
 
+impl Ast {
+    /// `Ast::try_from` と同じく読むが、空白と改行は読み飛ばす
+    pub fn parse_skipping_whitespace(source: &str) -> Result<Self, InvalidError> {
+        parse(source, Sequence::parse_skipping_whitespace(source)?)
+    }
+}
+
+// 構造のエラーの位置は、seq を読んだ source を前から読み直して求める
+fn parse(source: &str, seq: Sequence) -> Result<Ast, InvalidError> {
+    Ast::try_from(seq).map_err(|_| InvalidError::from(diagnostic::structure_error(source)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,4 +261,30 @@ mod tests {
 
         assert_eq!(ast, Err(FormulaError::SurplusTokens));
     }
+
+    #[test]
+    fn size_and_depth() {
+        let ast = Ast::try_from("``s`ksk").unwrap();
+        assert_eq!(ast.size(), 4);
+        assert_eq!(ast.depth(), 3);
+        assert_eq!(Ast::Leaf(S).depth(), 0);
+    }
+
+    #[test]
+    fn deep_function_side() {
+        // ```...`ss...s の関数側に深い木
+        let mut ast = Ast::Leaf(S);
+        for _ in 0..1_000_000 {
+            ast = Ast::Apply(AstInner::new(ast, Ast::Leaf(K)));
+        }
+        let seq = Sequence::from(ast);
+        let ast = Ast::try_from(seq).unwrap();
+        assert_eq!(ast.depth(), 1_000_000);
+        let (function, argument) = match ast {
+            Ast::Apply(inner) => inner.into_parts(),
+            Ast::Leaf(_) => unreachable!(),
+        };
+        assert_eq!(argument, Ast::Leaf(K));
+        assert_eq!(function.size(), 1_000_000);
+    }
 }
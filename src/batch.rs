@@ -0,0 +1,310 @@
+//! 1行に1つの項を並列に評価し、行ごとの結果を TSV か JSON Lines か列を揃えたテキストで書き出す
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ast::Ast;
+use crate::error::{EvalError, InvalidError};
+use crate::eval::Config;
+use crate::sequence::Sequence;
+use crate::stack::Stax;
+
+/// 評価に失敗した理由の分類
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum ErrorKind {
+    Lexical,
+    Formula,
+    OutOfFuel,
+    Diverges,
+    Grows,
+    TooManyNodes,
+    StackTooDeep,
+    TooManyAllocations,
+    Cancelled,
+    TimedOut,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Lexical => write!(f, "lexical"),
+            ErrorKind::Formula => write!(f, "formula"),
+            ErrorKind::OutOfFuel => write!(f, "out_of_fuel"),
+            ErrorKind::Diverges => write!(f, "diverges"),
+            ErrorKind::Grows => write!(f, "grows"),
+            ErrorKind::TooManyNodes => write!(f, "too_many_nodes"),
+            ErrorKind::StackTooDeep => write!(f, "stack_too_deep"),
+            ErrorKind::TooManyAllocations => write!(f, "too_many_allocations"),
+            ErrorKind::Cancelled => write!(f, "cancelled"),
+            ErrorKind::TimedOut => write!(f, "timed_out"),
+        }
+    }
+}
+
+/// 1行の評価結果
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Record {
+    /// 1から始まる行番号
+    pub line: usize,
+    pub input: String,
+    pub output: Result<Sequence, ErrorKind>,
+    /// 規則を適用した回数
+    pub steps: u64,
+    /// `Stax` のスタックの長さの最大値
+    pub max_depth: usize,
+    pub elapsed: Duration,
+}
+
+/// 書き出しの形式
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Format {
+    Tsv,
+    JsonLines,
+    /// 人が読むための、列の幅を固定したテキスト。可変長の値は最後の列に置く
+    Text,
+}
+
+impl Format {
+    /// 先頭に置く行。なければ `None`
+    pub fn header(self) -> Option<&'static str> {
+        match self {
+            Format::Tsv => Some("line\tinput\toutput\tsteps\tmax_depth\telapsed_ns\terror"),
+            Format::JsonLines => None,
+            Format::Text => {
+                Some("  line       steps  max_depth    elapsed_ns  error                 output")
+            }
+        }
+    }
+
+    /// `record` を改行を含まない1行にする。テキストでは入力を省き、行番号で指す
+    pub fn format(self, record: &Record) -> String {
+        let (output, error) = match &record.output {
+            Ok(seq) => (Some(String::from(seq.clone())), None),
+            Err(kind) => (None, Some(kind.to_string())),
+        };
+        match self {
+            Format::Tsv => format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                record.line,
+                tsv_field(&record.input),
+                output.unwrap_or_default(),
+                record.steps,
+                record.max_depth,
+                record.elapsed.as_nanos(),
+                error.unwrap_or_default()
+            ),
+            Format::JsonLines => format!(
+                "{{\"line\":{},\"input\":{},\"output\":{},\"steps\":{},\"max_depth\":{},\"elapsed_ns\":{},\"error\":{}}}",
+                record.line,
+                json_string(&record.input),
+                output.as_deref().map_or("null".to_string(), json_string),
+                record.steps,
+                record.max_depth,
+                record.elapsed.as_nanos(),
+                error.as_deref().map_or("null".to_string(), json_string)
+            ),
+            Format::Text => format!(
+                "{:>6}  {:>10}  {:>9}  {:>12}  {:<20}  {}",
+                record.line,
+                record.steps,
+                record.max_depth,
+                record.elapsed.as_nanos(),
+                error.unwrap_or_default(),
+                output.unwrap_or_default()
+            )
+            .trim_end()
+            .to_string(),
+        }
+    }
+}
+
+pub(crate) fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Unlambda 記法の1行を `Stax` で `config` の制限の下で評価する。
+/// CLI の `eval` と同じく `Ast::parse_skipping_whitespace` で読むので、空白を挟んでもよい
+pub fn evaluate_line(line: usize, input: &str, config: &Config) -> Record {
+    let start = Instant::now();
+    let (output, steps, max_depth) = match Ast::parse_skipping_whitespace(input) {
+        Err(InvalidError::LexiconError(_)) => (Err(ErrorKind::Lexical), 0, 0),
+        Err(InvalidError::FormulaError(_)) => (Err(ErrorKind::Formula), 0, 0),
+        Ok(ast) => {
+            let mut stax = Stax::from(Sequence::from(ast));
+            let output = stax.eval_with(config).map_err(|e| match e {
+                EvalError::FormulaError(_) => ErrorKind::Formula,
+                EvalError::OutOfFuel => ErrorKind::OutOfFuel,
+                EvalError::Diverges { .. } => ErrorKind::Diverges,
+                EvalError::Grows { .. } => ErrorKind::Grows,
+                EvalError::TooManyNodes { .. } => ErrorKind::TooManyNodes,
+                EvalError::StackTooDeep { .. } => ErrorKind::StackTooDeep,
+                EvalError::TooManyAllocations { .. } => ErrorKind::TooManyAllocations,
+                EvalError::Cancelled { .. } => ErrorKind::Cancelled,
+                EvalError::TimedOut { .. } => ErrorKind::TimedOut,
+            });
+            (output, stax.steps(), stax.max_depth())
+        }
+    };
+    Record {
+        line,
+        input: input.to_string(),
+        output,
+        steps,
+        max_depth,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// 空行を除いた全ての行を `jobs` 個のスレッドで評価し、行の順に返す
+pub fn evaluate_all(lines: &[String], config: &Config, jobs: usize) -> Vec<Record> {
+    let work: Vec<(usize, &str)> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, line.as_str()))
+        .collect();
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::with_capacity(work.len()));
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| {
+                let mut records = Vec::new();
+                while let Some((line, input)) = work.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    records.push(evaluate_line(*line, input, config));
+                }
+                done.lock().unwrap().extend(records);
+            });
+        }
+    });
+    let mut records = done.into_inner().unwrap();
+    records.sort_by_key(|record| record.line);
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn seq(s: &str) -> Sequence {
+        Sequence::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn lines() {
+        let record = evaluate_line(1, "```sski", &Config::default());
+        assert_eq!(record.output, Ok(seq("``si`ki")));
+        assert_eq!(record.steps, 1);
+        assert_eq!(record.max_depth, 4);
+        assert_eq!(
+            evaluate_line(2, "`sx", &Config::default()).output,
+            Err(ErrorKind::Lexical)
+        );
+        assert_eq!(
+            evaluate_line(3, "``sk", &Config::default()).output,
+            Err(ErrorKind::Formula)
+        );
+        // eval と同じく、項の途中の空白は読み飛ばす
+        let record = evaluate_line(3, " ```s k  k\t`ki ", &Config::default());
+        assert_eq!(record.output, Ok(seq("`ki")));
+        assert_eq!(record.input, " ```s k  k\t`ki ");
+        let record = evaluate_line(4, "```sii``sii", &Config::with_fuel(100));
+        assert_eq!(record.output, Err(ErrorKind::OutOfFuel));
+        assert_eq!(record.steps, 100);
+        let config = Config {
+            detect_loops: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            evaluate_line(5, "```sii``sii", &config).output,
+            Err(ErrorKind::Diverges)
+        );
+        let config = Config {
+            max_stack: Some(2),
+            ..Config::default()
+        };
+        assert_eq!(
+            evaluate_line(6, "```sii``sii", &config).output,
+            Err(ErrorKind::StackTooDeep)
+        );
+    }
+
+    #[test]
+    fn parallel() {
+        let lines: Vec<String> = (0..200)
+            .map(|n| match n % 4 {
+                0 => "```sski".to_string(),
+                1 => "```sii``sii".to_string(),
+                2 => String::new(),
+                _ => "`?".to_string(),
+            })
+            .collect();
+        let records = evaluate_all(&lines, &Config::with_fuel(50), 4);
+        assert_eq!(records.len(), 150);
+        for record in &records {
+            let expected = match (record.line - 1) % 4 {
+                0 => Ok(seq("``si`ki")),
+                1 => Err(ErrorKind::OutOfFuel),
+                _ => Err(ErrorKind::Lexical),
+            };
+            assert_eq!(record.output, expected, "line {}", record.line);
+        }
+        assert!(records.windows(2).all(|pair| pair[0].line < pair[1].line));
+    }
+
+    #[test]
+    fn formats() {
+        let mut record = evaluate_line(1, "```sski", &Config::default());
+        record.elapsed = Duration::from_nanos(1500);
+        assert_eq!(
+            Format::Tsv.format(&record),
+            "1\t```sski\t``si`ki\t1\t4\t1500\t"
+        );
+        assert_eq!(
+            Format::JsonLines.format(&record),
+            r#"{"line":1,"input":"```sski","output":"``si`ki","steps":1,"max_depth":4,"elapsed_ns":1500,"error":null}"#
+        );
+        assert_eq!(
+            Format::Text.format(&record),
+            "     1           1          4          1500                        ``si`ki"
+        );
+
+        let mut record = evaluate_line(7, "`s\t\"", &Config::default());
+        record.elapsed = Duration::from_nanos(0);
+        assert_eq!(
+            Format::Tsv.format(&record),
+            "7\t`s\\t\"\t\t0\t0\t0\tlexical"
+        );
+        assert_eq!(
+            Format::JsonLines.format(&record),
+            r#"{"line":7,"input":"`s\t\"","output":null,"steps":0,"max_depth":0,"elapsed_ns":0,"error":"lexical"}"#
+        );
+        assert_eq!(
+            Format::Text.format(&record),
+            "     7           0          0             0  lexical"
+        );
+    }
+}
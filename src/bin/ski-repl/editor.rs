@@ -0,0 +1,294 @@
+//! 外部クレートを使わない1行編集と履歴
+//!
+//! 端末の設定は `stty` で切り替える。標準入力が端末でなければ1行ずつそのまま読む。
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+pub struct Editor {
+    history: Vec<String>,
+    file: Option<PathBuf>,
+    // 起動時の端末の設定 (`stty -g`)。端末でなければ None
+    terminal: Option<String>,
+}
+
+impl Editor {
+    /// `file` があれば履歴をそこから読み、追加した行をそこに書き足す
+    pub fn new(file: Option<PathBuf>) -> Self {
+        let history = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|s| s.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Editor {
+            history,
+            file,
+            terminal: stty(&["-g"]),
+        }
+    }
+
+    /// 1行読む。入力の終わりならば `None`
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let terminal = match &self.terminal {
+            Some(terminal) => terminal.clone(),
+            None => {
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                return Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()));
+            }
+        };
+        if stty(&["raw", "-echo"]).is_none() {
+            return Err(io::Error::other("stty failed"));
+        }
+        let _restore = Restore(terminal);
+        self.edit(prompt)
+    }
+
+    /// 空行と直前と同じ行を除いて履歴に加える
+    pub fn add_history(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+        self.history.push(line.to_string());
+        match &self.file {
+            Some(file) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(file)?;
+                writeln!(file, "{}", line)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+
+        let mut line = Line::default();
+        // 履歴の中の位置。history.len() ならば編集中の行
+        let mut index = self.history.len();
+        let mut draft = Vec::new();
+        line.redraw(&mut out, prompt)?;
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Some(line.chars.iter().collect()));
+                }
+                Key::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Ok(Some(String::new()));
+                }
+                Key::Eof if line.chars.is_empty() => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(None);
+                }
+                Key::Eof | Key::Delete => {
+                    if line.cursor < line.chars.len() {
+                        line.chars.remove(line.cursor);
+                    }
+                }
+                Key::Backspace => {
+                    if line.cursor > 0 {
+                        line.cursor -= 1;
+                        line.chars.remove(line.cursor);
+                    }
+                }
+                Key::Left => line.cursor = line.cursor.saturating_sub(1),
+                Key::Right => line.cursor = (line.cursor + 1).min(line.chars.len()),
+                Key::Home => line.cursor = 0,
+                Key::End => line.cursor = line.chars.len(),
+                Key::KillEnd => line.chars.truncate(line.cursor),
+                Key::KillStart => {
+                    line.chars.drain(..line.cursor);
+                    line.cursor = 0;
+                }
+                Key::Up => {
+                    if index > 0 {
+                        if index == self.history.len() {
+                            draft = line.chars.clone();
+                        }
+                        index -= 1;
+                        line.set(self.history[index].chars().collect());
+                    }
+                }
+                Key::Down => {
+                    if index < self.history.len() {
+                        index += 1;
+                        if index == self.history.len() {
+                            line.set(draft.clone());
+                        } else {
+                            line.set(self.history[index].chars().collect());
+                        }
+                    }
+                }
+                Key::Char(c) => {
+                    line.chars.insert(line.cursor, c);
+                    line.cursor += 1;
+                }
+                Key::Ignored => {}
+            }
+            line.redraw(&mut out, prompt)?;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn set(&mut self, chars: Vec<char>) {
+        self.cursor = chars.len();
+        self.chars = chars;
+    }
+
+    fn redraw(&self, out: &mut dyn Write, prompt: &str) -> io::Result<()> {
+        let text: String = self.chars.iter().collect();
+        write!(out, "\r{}{}\x1b[K", prompt, text)?;
+        let back = self.chars.len() - self.cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        out.flush()
+    }
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Interrupt,
+    Eof,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillEnd,
+    KillStart,
+    Ignored,
+}
+
+fn read_byte(input: &mut dyn Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn read_key(input: &mut dyn Read) -> io::Result<Option<Key>> {
+    let byte = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x08 | 0x7f => Key::Backspace,
+        0x0b => Key::KillEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillStart,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Ignored,
+        byte => read_char(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+// ESC [ A のような CSI 列と ESC O A のような SS3 列
+fn read_escape(input: &mut dyn Read) -> io::Result<Key> {
+    match read_byte(input)? {
+        Some(b'[') | Some(b'O') => {}
+        _ => return Ok(Key::Ignored),
+    }
+    let mut parameter = Vec::new();
+    loop {
+        match read_byte(input)? {
+            Some(byte @ b'0'..=b'9') | Some(byte @ b';') => parameter.push(byte),
+            Some(b'A') => return Ok(Key::Up),
+            Some(b'B') => return Ok(Key::Down),
+            Some(b'C') => return Ok(Key::Right),
+            Some(b'D') => return Ok(Key::Left),
+            Some(b'H') => return Ok(Key::Home),
+            Some(b'F') => return Ok(Key::End),
+            Some(b'~') => {
+                return Ok(match &parameter[..] {
+                    b"1" | b"7" => Key::Home,
+                    b"4" | b"8" => Key::End,
+                    b"3" => Key::Delete,
+                    _ => Key::Ignored,
+                })
+            }
+            _ => return Ok(Key::Ignored),
+        }
+    }
+}
+
+// UTF-8 の残りのバイトを読んで1文字にする
+fn read_char(input: &mut dyn Read, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    Ok(match std::str::from_utf8(&bytes) {
+        Ok(s) => s.chars().next().map_or(Key::Ignored, Key::Char),
+        Err(_) => Key::Ignored,
+    })
+}
+
+// 標準入力の端末に対して stty を実行し、成功すれば出力を返す
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+// 破棄されるときに端末の設定を戻す
+struct Restore(String);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        stty(&[&self.0]);
+    }
+}
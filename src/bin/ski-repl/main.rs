@@ -0,0 +1,43 @@
+//! SKI の対話環境
+//!
+//! 履歴はカレントディレクトリの `.ski_history` に保存する。
+
+mod editor;
+mod session;
+
+use std::io;
+use std::path::PathBuf;
+
+use editor::Editor;
+use session::{Control, Session};
+
+const HISTORY_FILE: &str = ".ski_history";
+
+fn main() {
+    let mut editor = Editor::new(Some(PathBuf::from(HISTORY_FILE)));
+    let mut session = Session::new();
+    let stdout = io::stdout();
+    let mut history_failed = false;
+    loop {
+        let line = match editor.read_line("ski> ") {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        };
+        if let Err(e) = editor.add_history(&line) {
+            // 毎行同じ警告を出さない
+            if !history_failed {
+                eprintln!("warning: could not save history: {}", e);
+                history_failed = true;
+            }
+        }
+        match session.execute(&line, &mut stdout.lock()) {
+            Ok(Control::Continue) => {}
+            Ok(Control::Quit) => break,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
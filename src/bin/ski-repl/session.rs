@@ -0,0 +1,532 @@
+//! REPL の状態と、1行ごとのコマンドの解釈
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use ski_rs::ast::Ast;
+use ski_rs::error::{InvalidError, NotationError};
+use ski_rs::notation::Applicative;
+use ski_rs::reduce::{self, Strategy, Trace};
+use ski_rs::types::{self, TypeError};
+
+const DEFAULT_FUEL: u64 = 10_000;
+
+const HELP: &str = "\
+term                 evaluate term to normal form
+let name = term      define name (lowercase, [a-z_][a-z0-9_]*)
+:step [term]         reduce one step (continues the last term if omitted)
+:trace term          show every step until normal form
+:type term           show the principal simple type of term
+:size term           show the number of atoms and tokens
+:strategy [name]     show or set the strategy (normal, applicative)
+:load file           run each line of file
+:fuel [n]            show or set the maximum number of steps
+:help                show this message
+:quit                exit
+
+Terms are written as S(KS)K, or as ``s`ksk when they start with `.";
+
+/// 1行を実行した後に REPL を続けるか
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Control {
+    Continue,
+    Quit,
+}
+
+#[derive(Debug)]
+pub enum ReplError {
+    Invalid(InvalidError),
+    Notation(NotationError),
+    /// `fuel` 回の簡約で正規形に至らなかった
+    OutOfFuel {
+        steps: u64,
+        term: Ast,
+    },
+    Io(io::Error),
+    Usage(&'static str),
+    UnknownCommand(String),
+    BadName(String),
+    /// `:step` に続けるべき項がない
+    NothingToStep,
+    /// 単純型が付かない
+    Type(TypeError),
+    /// 読み込み中のファイルをもう一度 `:load` した
+    LoadCycle(String),
+    Load {
+        file: String,
+        line: usize,
+        error: Box<ReplError>,
+    },
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(err) => write!(f, "invalid term: {}", err),
+            Self::Notation(err) => write!(f, "invalid term: {}", err),
+            Self::OutOfFuel { steps, term } => write!(
+                f,
+                "no normal form within {} steps, stopped at {}",
+                steps,
+                Applicative::from(term.clone())
+            ),
+            Self::Io(err) => err.fmt(f),
+            Self::Usage(usage) => write!(f, "usage: {}", usage),
+            Self::UnknownCommand(command) => {
+                write!(f, "unknown command {} (try :help)", command)
+            }
+            Self::BadName(name) => write!(f, "{:?} is not a valid name", name),
+            Self::NothingToStep => write!(f, "no term to step"),
+            Self::Type(err) => write!(f, "no simple type: {}", err),
+            Self::LoadCycle(file) => write!(f, "{} is already being loaded", file),
+            Self::Load { file, line, error } => write!(f, "{}:{}: {}", file, line, error),
+        }
+    }
+}
+
+impl From<InvalidError> for ReplError {
+    fn from(e: InvalidError) -> Self {
+        ReplError::Invalid(e)
+    }
+}
+
+impl From<NotationError> for ReplError {
+    fn from(e: NotationError) -> Self {
+        ReplError::Notation(e)
+    }
+}
+
+impl From<TypeError> for ReplError {
+    fn from(e: TypeError) -> Self {
+        ReplError::Type(e)
+    }
+}
+
+impl From<io::Error> for ReplError {
+    fn from(e: io::Error) -> Self {
+        ReplError::Io(e)
+    }
+}
+
+pub struct Session {
+    definitions: HashMap<String, Ast>,
+    strategy: Strategy,
+    fuel: u64,
+    // :step で簡約中の項と、そこまでのステップ数
+    stepping: Option<(u64, Ast)>,
+    // :load で読み込み中のファイル。自分自身を読み込む循環を見つける
+    loading: Vec<PathBuf>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            definitions: HashMap::new(),
+            strategy: Strategy::Normal,
+            fuel: DEFAULT_FUEL,
+            stepping: None,
+            loading: Vec::new(),
+        }
+    }
+
+    /// 1行を実行し、結果を `out` に書く
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> Result<Control, ReplError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(Control::Continue);
+        }
+        if let Some(rest) = line.strip_prefix(':') {
+            let (command, argument) = match rest.find(char::is_whitespace) {
+                Some(i) => (&rest[..i], rest[i..].trim()),
+                None => (rest, ""),
+            };
+            return self.command(command, argument, out);
+        }
+        if let Some(rest) = line.strip_prefix("let ") {
+            self.define(rest, out)?;
+            return Ok(Control::Continue);
+        }
+
+        let ast = self.parse(line)?;
+        let (_, normal) = self.normalize(ast)?;
+        writeln!(out, "{}", Applicative::from(normal))?;
+        Ok(Control::Continue)
+    }
+
+    fn command(
+        &mut self,
+        command: &str,
+        argument: &str,
+        out: &mut dyn Write,
+    ) -> Result<Control, ReplError> {
+        match command {
+            "step" | "s" => self.step(argument, out)?,
+            "trace" | "t" => self.trace(argument, out)?,
+            "type" => {
+                let ast = self.parse_required(argument, ":type term")?;
+                let ty = types::infer(&ast)?;
+                writeln!(out, "{} : {}", Applicative::from(ast), ty)?;
+            }
+            "size" => {
+                let leaves = self.parse_required(argument, ":size term")?.size();
+                writeln!(out, "{} atoms, {} tokens", leaves, 2 * leaves - 1)?;
+            }
+            "strategy" => {
+                match argument {
+                    "" => {}
+                    "normal" => self.strategy = Strategy::Normal,
+                    "applicative" => self.strategy = Strategy::Applicative,
+                    _ => return Err(ReplError::Usage(":strategy [normal|applicative]")),
+                }
+                writeln!(out, "strategy: {}", strategy_name(self.strategy))?;
+            }
+            "fuel" => {
+                if !argument.is_empty() {
+                    self.fuel = argument
+                        .parse()
+                        .map_err(|_| ReplError::Usage(":fuel [n]"))?;
+                }
+                writeln!(out, "fuel: {}", self.fuel)?;
+            }
+            "load" | "l" => {
+                if argument.is_empty() {
+                    return Err(ReplError::Usage(":load file"));
+                }
+                return self.load(argument, out);
+            }
+            "help" | "h" | "?" => writeln!(out, "{}", HELP)?,
+            "quit" | "q" => return Ok(Control::Quit),
+            _ => return Err(ReplError::UnknownCommand(format!(":{}", command))),
+        }
+        Ok(Control::Continue)
+    }
+
+    fn define(&mut self, rest: &str, out: &mut dyn Write) -> Result<(), ReplError> {
+        let (name, term) = match rest.find('=') {
+            Some(i) => (rest[..i].trim(), rest[i + 1..].trim()),
+            None => return Err(ReplError::Usage("let name = term")),
+        };
+        if !is_name(name) {
+            return Err(ReplError::BadName(name.to_string()));
+        }
+        let ast = self.parse(term)?;
+        writeln!(out, "{} = {}", name, Applicative::from(ast.clone()))?;
+        self.definitions.insert(name.to_string(), ast);
+        Ok(())
+    }
+
+    fn step(&mut self, argument: &str, out: &mut dyn Write) -> Result<(), ReplError> {
+        let (number, ast) = if argument.is_empty() {
+            self.stepping.take().ok_or(ReplError::NothingToStep)?
+        } else {
+            (0, self.parse(argument)?)
+        };
+        match reduce::step(&ast, self.strategy) {
+            Some((redex, term)) => {
+                writeln!(
+                    out,
+                    "{}: {} at {}  {}",
+                    number + 1,
+                    redex.rule,
+                    redex.path,
+                    Applicative::from(term.clone())
+                )?;
+                self.stepping = Some((number + 1, term));
+            }
+            None => writeln!(out, "normal form: {}", Applicative::from(ast))?,
+        }
+        Ok(())
+    }
+
+    fn trace(&mut self, argument: &str, out: &mut dyn Write) -> Result<(), ReplError> {
+        let ast = self.parse_required(argument, ":trace term")?;
+        writeln!(out, "{:>4}  {}", 0, Applicative::from(ast.clone()))?;
+        let mut trace = Trace::new(ast, self.strategy);
+        for step in trace.by_ref().take(self.fuel as usize) {
+            writeln!(
+                out,
+                "{:>4}  {}    ({} at {})",
+                step.number,
+                Applicative::from(step.term),
+                step.redex.rule,
+                step.redex.path
+            )?;
+        }
+        if reduce::select(trace.term(), self.strategy).is_some() {
+            return Err(ReplError::OutOfFuel {
+                steps: self.fuel,
+                term: trace.term().clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, file: &str, out: &mut dyn Write) -> Result<Control, ReplError> {
+        let io_error = |e: io::Error| ReplError::Load {
+            file: file.to_string(),
+            line: 0,
+            error: Box::new(e.into()),
+        };
+        let path = fs::canonicalize(file).map_err(io_error)?;
+        if self.loading.contains(&path) {
+            return Err(ReplError::LoadCycle(file.to_string()));
+        }
+        let source = fs::read_to_string(&path).map_err(io_error)?;
+        self.loading.push(path);
+        let result = self.run_lines(file, &source, out);
+        self.loading.pop();
+        result
+    }
+
+    fn run_lines(
+        &mut self,
+        file: &str,
+        source: &str,
+        out: &mut dyn Write,
+    ) -> Result<Control, ReplError> {
+        for (i, line) in source.lines().enumerate() {
+            match self.execute(line, out) {
+                Ok(Control::Continue) => {}
+                Ok(Control::Quit) => break,
+                Err(error) => {
+                    return Err(ReplError::Load {
+                        file: file.to_string(),
+                        line: i + 1,
+                        error: Box::new(error),
+                    })
+                }
+            }
+        }
+        Ok(Control::Continue)
+    }
+
+    // 正規形とそこまでのステップ数
+    fn normalize(&self, ast: Ast) -> Result<(u64, Ast), ReplError> {
+        let mut ast = ast;
+        let mut steps = 0;
+        while let Some((_, reduct)) = reduce::step(&ast, self.strategy) {
+            if steps == self.fuel {
+                return Err(ReplError::OutOfFuel { steps, term: ast });
+            }
+            ast = reduct;
+            steps += 1;
+        }
+        Ok((steps, ast))
+    }
+
+    // ` で始まれば Unlambda 記法、そうでなければ定義済みの名前を使える関数適用記法
+    fn parse(&self, source: &str) -> Result<Ast, ReplError> {
+        let source = source.trim();
+        if source.starts_with('`') {
+            return Ok(Ast::try_from(source)?);
+        }
+        let app =
+            Applicative::parse_with_names(source, &|name| self.definitions.get(name).cloned())?;
+        Ok(Ast::from(app))
+    }
+
+    fn parse_required(&self, source: &str, usage: &'static str) -> Result<Ast, ReplError> {
+        if source.is_empty() {
+            return Err(ReplError::Usage(usage));
+        }
+        self.parse(source)
+    }
+}
+
+fn strategy_name(strategy: Strategy) -> &'static str {
+    match strategy {
+        Strategy::Normal => "normal",
+        Strategy::Applicative => "applicative",
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some('a'..='z') | Some('_'))
+        && chars.all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(session: &mut Session, line: &str) -> Result<String, ReplError> {
+        let mut out = Vec::new();
+        session.execute(line, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "SKKS").unwrap(), "S\n");
+        assert_eq!(run(&mut session, "```skks").unwrap(), "S\n");
+        assert_eq!(run(&mut session, "").unwrap(), "");
+        assert!(matches!(
+            run(&mut session, "S(K"),
+            Err(ReplError::Notation(NotationError::UnclosedParen(1)))
+        ));
+        assert!(matches!(
+            run(&mut session, "``sk"),
+            Err(ReplError::Invalid(_))
+        ));
+        assert!(matches!(
+            run(&mut session, "SII(SII)"),
+            Err(ReplError::OutOfFuel { steps: 10_000, .. })
+        ));
+    }
+
+    #[test]
+    fn definitions() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "let id = SKK").unwrap(), "id = SKK\n");
+        assert_eq!(run(&mut session, "let b = S(KS)K").unwrap(), "b = S(KS)K\n");
+        assert_eq!(run(&mut session, "b id id K").unwrap(), "K\n");
+        assert!(matches!(
+            run(&mut session, "let X = K"),
+            Err(ReplError::BadName(_))
+        ));
+        assert!(matches!(
+            run(&mut session, "c K"),
+            Err(ReplError::Notation(NotationError::UnknownName(0, _)))
+        ));
+    }
+
+    #[test]
+    fn stepping() {
+        let mut session = Session::new();
+        assert_eq!(
+            run(&mut session, ":step SKKS").unwrap(),
+            "1: S at ε  KS(KS)\n"
+        );
+        assert_eq!(run(&mut session, ":step").unwrap(), "2: K at ε  S\n");
+        assert_eq!(run(&mut session, ":step").unwrap(), "normal form: S\n");
+        assert!(matches!(
+            run(&mut session, ":step"),
+            Err(ReplError::NothingToStep)
+        ));
+
+        let trace = run(&mut session, ":trace SKKS").unwrap();
+        assert_eq!(
+            trace,
+            "   0  SKKS\n   1  KS(KS)    (S at ε)\n   2  S    (K at ε)\n"
+        );
+        run(&mut session, ":fuel 1").unwrap();
+        assert!(matches!(
+            run(&mut session, ":trace SKKS"),
+            Err(ReplError::OutOfFuel { steps: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn strategies() {
+        let mut session = Session::new();
+        // K I (SII(SII)) は正規順序でのみ正規形に至る
+        run(&mut session, ":fuel 100").unwrap();
+        assert_eq!(run(&mut session, "KI(SII(SII))").unwrap(), "I\n");
+        assert_eq!(
+            run(&mut session, ":strategy applicative").unwrap(),
+            "strategy: applicative\n"
+        );
+        assert!(matches!(
+            run(&mut session, "KI(SII(SII))"),
+            Err(ReplError::OutOfFuel { .. })
+        ));
+        assert!(matches!(
+            run(&mut session, ":strategy lazy"),
+            Err(ReplError::Usage(_))
+        ));
+        assert!(matches!(
+            run(&mut session, ":fuel many"),
+            Err(ReplError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn inspect() {
+        let mut session = Session::new();
+        assert_eq!(
+            run(&mut session, ":size S(KS)K").unwrap(),
+            "4 atoms, 7 tokens\n"
+        );
+        assert_eq!(run(&mut session, ":type SK").unwrap(), "SK : (a→b)→a→a\n");
+        assert_eq!(
+            run(&mut session, ":type S(KS)K").unwrap(),
+            "S(KS)K : (a→b)→(c→a)→c→b\n"
+        );
+        assert!(matches!(
+            run(&mut session, ":type SII"),
+            Err(ReplError::Type(_))
+        ));
+        assert!(matches!(
+            run(&mut session, ":frobnicate"),
+            Err(ReplError::UnknownCommand(_))
+        ));
+        assert_eq!(
+            session.execute(":quit", &mut Vec::new()).unwrap(),
+            Control::Quit
+        );
+    }
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join(format!("ski-repl-load-{}.ski", std::process::id()));
+        fs::write(&path, "# combinators\nlet b = S(KS)K\n\nlet c = S(d b)K\n").unwrap();
+        let mut session = Session::new();
+        let file = path.to_str().unwrap();
+        let result = run(&mut session, &format!(":load {}", file));
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(ReplError::Load { line, error, .. }) => {
+                assert_eq!(line, 4);
+                assert!(matches!(
+                    *error,
+                    ReplError::Notation(NotationError::UnknownName(2, _))
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+        // 失敗した行より前の定義は残る
+        assert_eq!(run(&mut session, "b K K K").unwrap(), "K(KK)\n");
+        assert!(matches!(
+            run(&mut session, ":load /nonexistent/file.ski"),
+            Err(ReplError::Load { line: 0, .. })
+        ));
+
+        // 自分自身を読み込むファイルは、溢れずに循環として報告する
+        let dir = std::env::temp_dir();
+        let a = dir.join(format!("ski-repl-cycle-a-{}.ski", std::process::id()));
+        let b = dir.join(format!("ski-repl-cycle-b-{}.ski", std::process::id()));
+        fs::write(&a, format!("let x = K\n:load {}\n", b.display())).unwrap();
+        fs::write(&b, format!(":load {}\n", a.display())).unwrap();
+        let result = run(&mut session, &format!(":load {}", a.display()));
+        fs::remove_file(&a).unwrap();
+        fs::remove_file(&b).unwrap();
+        match result {
+            Err(ReplError::Load { line: 2, error, .. }) => match *error {
+                ReplError::Load { line: 1, error, .. } => {
+                    assert!(matches!(*error, ReplError::LoadCycle(_)))
+                }
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+        // 読み込みを終えたファイルは、もう一度読み込める
+        fs::write(&b, "let y = S\n").unwrap();
+        let result = run(&mut session, &format!(":load {}", b.display()));
+        let again = run(&mut session, &format!(":load {}", b.display()));
+        fs::remove_file(&b).unwrap();
+        assert_eq!(result.unwrap(), "y = S\n");
+        assert_eq!(again.unwrap(), "y = S\n");
+    }
+}
@@ -0,0 +1,255 @@
+//! ラムダ式からSKI formulaへの変換 (bracket abstraction)
+
+use crate::ast::{Ast, AstInner};
+use crate::error::NotationError;
+use crate::symbolic::Term;
+use crate::token::Atom;
+
+/// 閉じたラムダ式を読み、同じ振る舞いをするSKI formulaにする。
+///
+/// 抽象は `\x. M` か `λx. M` で書き、`\x y. M` は `\x. \y. M` と同じ。
+/// 変数名は `[a-z_][a-z0-9_]*`、大文字の `S`, `K`, `I` はそのコンビネータを表す。
+/// 適用は左結合で、抽象の本体はできるだけ右まで伸びる。
+/// 束縛されていない変数は `NotationError::UnknownName` になる。
+pub fn compile(s: &str) -> Result<Ast, NotationError> {
+    let mut parser = Parser {
+        chars: s.chars().collect(),
+        point: 0,
+        scope: Vec::new(),
+        fresh: 0,
+    };
+    let term = parser.term()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(closed(term)),
+        Some(')') => Err(NotationError::UnmatchedParen(parser.point as u32)),
+        Some(c) => Err(NotationError::UnexpectedChar(parser.point as u32, c)),
+    }
+}
+
+/// `term` から変数 `x` を抜き出す。結果を `x` に適用すると `term` に簡約される
+///
+/// 規則は次の順に試す。
+///
+/// * `[x] M = K M` (`M` に `x` が現れない)
+/// * `[x] x = I`
+/// * `[x] (M x) = M` (`M` に `x` が現れない)
+/// * `[x] (M N) = S ([x] M) ([x] N)`
+pub fn abstract_var(x: usize, term: Term) -> Term {
+    if !occurs(x, &term) {
+        return Term::Atom(Atom::K).apply(term);
+    }
+    match term {
+        Term::Var(_) => Term::Atom(Atom::I),
+        Term::Apply(function, argument) => {
+            if *argument == Term::Var(x) && !occurs(x, &function) {
+                return *function;
+            }
+            Term::Atom(Atom::S)
+                .apply(abstract_var(x, *function))
+                .apply(abstract_var(x, *argument))
+        }
+        Term::Atom(_) => unreachable!(),
+    }
+}
+
+fn occurs(x: usize, term: &Term) -> bool {
+    match term {
+        Term::Atom(_) => false,
+        Term::Var(n) => *n == x,
+        Term::Apply(function, argument) => occurs(x, function) || occurs(x, argument),
+    }
+}
+
+// 束縛されていない変数は parser で弾いているので、全ての変数は抜き出し済み
+fn closed(term: Term) -> Ast {
+    match term {
+        Term::Atom(atom) => Ast::Leaf(atom),
+        Term::Var(_) => unreachable!(),
+        Term::Apply(function, argument) => {
+            Ast::Apply(AstInner::new(closed(*function), closed(*argument)))
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    point: usize,
+    // 束縛中の変数名と番号。後ろほど内側
+    scope: Vec<(String, usize)>,
+    fresh: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.point).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.point += 1;
+        }
+    }
+
+    // term := primary* abstraction?
+    fn term(&mut self) -> Result<Term, NotationError> {
+        let mut term: Option<Term> = None;
+        loop {
+            self.skip_whitespace();
+            let next = match self.peek() {
+                Some('\\') | Some('λ') => Some(self.abstraction()?),
+                _ => self.primary()?,
+            };
+            match next {
+                Some(next) => {
+                    term = Some(match term {
+                        Some(function) => function.apply(next),
+                        None => next,
+                    })
+                }
+                None => break,
+            }
+        }
+        term.ok_or(NotationError::EmptyTerm(self.point as u32))
+    }
+
+    fn abstraction(&mut self) -> Result<Term, NotationError> {
+        self.point += 1;
+        let mut bound = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('.') if !bound.is_empty() => {
+                    self.point += 1;
+                    break;
+                }
+                Some(c) if is_name_start(c) => {
+                    let id = self.fresh;
+                    self.fresh += 1;
+                    let name = self.name();
+                    self.scope.push((name, id));
+                    bound.push(id);
+                }
+                Some(c) => return Err(NotationError::UnexpectedChar(self.point as u32, c)),
+                None => return Err(NotationError::EmptyTerm(self.point as u32)),
+            }
+        }
+        let mut body = self.term()?;
+        for id in bound.into_iter().rev() {
+            self.scope.pop();
+            body = abstract_var(id, body);
+        }
+        Ok(body)
+    }
+
+    fn primary(&mut self) -> Result<Option<Term>, NotationError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let term = match c {
+            'S' => Term::Atom(Atom::S),
+            'K' => Term::Atom(Atom::K),
+            'I' => Term::Atom(Atom::I),
+            '(' => {
+                let open = self.point;
+                self.point += 1;
+                let term = self.term()?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(NotationError::UnclosedParen(open as u32));
+                }
+                self.point += 1;
+                return Ok(Some(term));
+            }
+            ')' | '.' => return Ok(None),
+            c if is_name_start(c) => {
+                let start = self.point;
+                let name = self.name();
+                return match self.scope.iter().rev().find(|(n, _)| *n == name) {
+                    Some((_, id)) => Ok(Some(Term::Var(*id))),
+                    None => Err(NotationError::UnknownName(start as u32, name)),
+                };
+            }
+            c => return Err(NotationError::UnexpectedChar(self.point as u32, c)),
+        };
+        self.point += 1;
+        Ok(Some(term))
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.point;
+        while let Some(c) = self.peek() {
+            if !matches!(c, 'a'..='z' | '0'..='9' | '_') {
+                break;
+            }
+            self.point += 1;
+        }
+        self.chars[start..self.point].iter().collect()
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    matches!(c, 'a'..='z' | '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbolic::equivalent;
+    use std::convert::TryFrom;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn compiles() {
+        assert_eq!(compile(r"\x. x"), Ok(ast("i")));
+        assert_eq!(compile(r"\x y. x"), Ok(ast("k")));
+        assert_eq!(compile(r"λx y. y"), Ok(ast("`ki")));
+        // 合成 B
+        assert_eq!(compile(r"\f g x. f (g x)"), Ok(ast("``s`ksk")));
+        assert_eq!(compile(r"(\x. x) S"), Ok(ast("`is")));
+        // 内側の束縛が外側を隠す
+        assert_eq!(compile(r"\x. \x. x"), Ok(ast("`ki")));
+    }
+
+    #[test]
+    fn behaves_like_lambda() {
+        // \x y z. x z (y z) は S と外延的に等しい
+        let s = compile(r"\x y z. x z (y z)").unwrap();
+        assert_eq!(
+            equivalent(&Term::from(s), &Term::Atom(Atom::S), 3, 100),
+            Some(true)
+        );
+        // C = \f x y. f y x
+        let c = Term::from(compile(r"\f x y. f y x").unwrap());
+        assert_eq!(
+            equivalent(
+                &c.apply(Term::Var(0)),
+                &Term::try_from("S(K(Sa))K").unwrap(),
+                2,
+                100
+            ),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            compile(r"\x. y"),
+            Err(NotationError::UnknownName(4, "y".to_string()))
+        );
+        assert_eq!(compile(r"\. x"), Err(NotationError::UnexpectedChar(1, '.')));
+        assert_eq!(compile(r"\x"), Err(NotationError::EmptyTerm(2)));
+        assert_eq!(compile(r"\x."), Err(NotationError::EmptyTerm(3)));
+        assert_eq!(compile(r"(\x. x"), Err(NotationError::UnclosedParen(0)));
+        assert_eq!(compile(r"\x. x)"), Err(NotationError::UnmatchedParen(5)));
+        assert_eq!(compile(""), Err(NotationError::EmptyTerm(0)));
+    }
+}
@@ -0,0 +1,212 @@
+//! `Stax` を1つずつ進めて中を見るためのデバッガ
+
+use crate::ast::Ast;
+use crate::error::EvalError;
+use crate::sequence::Sequence;
+use crate::stack::Stax;
+use crate::symbolic::Term;
+use crate::term::Ski;
+
+/// 評価を止める条件。条件が成り立たない状態から成り立つ状態に変わったときに止まる
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Breakpoint {
+    /// スタックの長さが `N` を超えた
+    StackDepth(usize),
+    /// パターンに合う値をスタックに積んだ。パターンの変数はどんな項にも合う (`Term::matches`)。
+    /// 積んだ値を毎回 `Ast` に直すので、その大きさに比例して遅くなる
+    Produces(Term),
+    /// 規則の適用回数が `K` に達した
+    Steps(u64),
+}
+
+/// `Debugger::step` と `Debugger::resume` の結果
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Event {
+    /// 1つ処理して、どのブレークポイントにも当たらなかった
+    Stepped,
+    /// 条件が成り立ったブレークポイントの番号
+    Break(Vec<usize>),
+    /// 評価が終わった。結果は `Debugger::result`
+    Finished,
+}
+
+/// `Stax` を program のトークンか後に回した仕事1つずつ進める
+pub struct Debugger {
+    stax: Stax,
+    breakpoints: Vec<Option<Breakpoint>>,
+    result: Option<Result<Sequence, EvalError>>,
+}
+
+impl From<Stax> for Debugger {
+    fn from(stax: Stax) -> Self {
+        Debugger {
+            stax,
+            breakpoints: Vec::new(),
+            result: None,
+        }
+    }
+}
+
+impl From<Sequence> for Debugger {
+    fn from(program: Sequence) -> Self {
+        Debugger::from(Stax::from(program))
+    }
+}
+
+impl Debugger {
+    /// ブレークポイントを加え、その番号を返す
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(Some(breakpoint));
+        self.breakpoints.len() - 1
+    }
+
+    /// ブレークポイントを外す。他のブレークポイントの番号は変わらない
+    pub fn remove_breakpoint(&mut self, id: usize) -> Option<Breakpoint> {
+        self.breakpoints.get_mut(id)?.take()
+    }
+
+    /// 1つ進める。ブレークポイントに当たれば `Event::Break`
+    pub fn step(&mut self) -> Event {
+        if self.result.is_some() {
+            return Event::Finished;
+        }
+        let (depth, steps) = (self.stax.stack().len(), self.stax.steps());
+        let pushed = match self.stax.step() {
+            Some(Ok(pushed)) => pushed,
+            Some(Err(e)) => return self.finish(Err(e)),
+            None => {
+                let result = self.stax.finish();
+                return self.finish(result);
+            }
+        };
+        let stack = self.stax.stack();
+        // 積んだ値は、パターンのブレークポイントがあるときだけ `Ast` に直す
+        let mut produced = None;
+        let hits: Vec<usize> = (0..self.breakpoints.len())
+            .filter(|&id| match &self.breakpoints[id] {
+                Some(Breakpoint::StackDepth(n)) => depth <= *n && stack.len() > *n,
+                Some(Breakpoint::Steps(k)) => steps < *k && self.stax.steps() >= *k,
+                Some(Breakpoint::Produces(pattern)) if pushed => pattern.matches(
+                    produced.get_or_insert_with(|| Ast::from(stack[stack.len() - 1].clone())),
+                ),
+                _ => false,
+            })
+            .collect();
+        if hits.is_empty() {
+            Event::Stepped
+        } else {
+            Event::Break(hits)
+        }
+    }
+
+    /// ブレークポイントに当たるか評価が終わるまで進める。
+    /// ブレークポイントがなければ、止まらない項では返らない
+    pub fn resume(&mut self) -> Event {
+        loop {
+            match self.step() {
+                Event::Stepped => {}
+                event => return event,
+            }
+        }
+    }
+
+    /// 評価をやめ、残りの計算を1つの項に戻して返す。正規形まで評価し終えていればその項
+    pub fn abort(self) -> Sequence {
+        match self.result {
+            Some(Ok(seq)) => seq,
+            _ => self.stax.snapshot(),
+        }
+    }
+
+    /// まだ処理していないトークン。後に回した仕事を含み、末尾から処理する
+    pub fn program(&self) -> Sequence {
+        self.stax.remaining()
+    }
+
+    /// 下から順のスタック
+    pub fn stack(&self) -> &[Ski] {
+        self.stax.stack()
+    }
+
+    /// これまでに適用した規則の回数
+    pub fn steps(&self) -> u64 {
+        self.stax.steps()
+    }
+
+    /// 評価が終わっていればその結果。エラーのときは、エラーになった仕事の前の状態で止まっている
+    pub fn result(&self) -> Option<&Result<Sequence, EvalError>> {
+        self.result.as_ref()
+    }
+
+    fn finish(&mut self, result: Result<Sequence, EvalError>) -> Event {
+        self.result = Some(result);
+        Event::Finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn seq(s: &str) -> Sequence {
+        Sequence::try_from(s).unwrap()
+    }
+
+    fn term(s: &str) -> Term {
+        Term::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn single_step() {
+        let mut debugger = Debugger::from(seq("```sski"));
+        assert_eq!(debugger.step(), Event::Stepped);
+        assert_eq!(debugger.program(), seq("```ssk"));
+        assert_eq!(debugger.stack(), &[Ski::I][..]);
+        let mut events = 1;
+        while debugger.step() != Event::Finished {
+            events += 1;
+        }
+        // トークン7つと、S の規則で後に回した仕事7つ
+        assert_eq!(events, 14);
+        assert_eq!(debugger.result(), Some(&Ok(seq("``si`ki"))));
+        assert_eq!(debugger.step(), Event::Finished);
+        assert_eq!(debugger.abort(), seq("``si`ki"));
+
+        let mut debugger = Debugger::from(seq("``sk"));
+        assert_eq!(debugger.resume(), Event::Finished);
+        assert!(matches!(debugger.result(), Some(Err(_))));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = Debugger::from(seq("```sski"));
+        let deep = debugger.add_breakpoint(Breakpoint::StackDepth(3));
+        assert_eq!(debugger.resume(), Event::Break(vec![deep]));
+        assert_eq!(debugger.stack(), &[Ski::I, Ski::K, Ski::S, Ski::S][..]);
+        assert_eq!(debugger.program(), seq("```"));
+        // 続きから再開する
+        assert_eq!(debugger.resume(), Event::Finished);
+        assert_eq!(debugger.result(), Some(&Ok(seq("``si`ki"))));
+
+        // ```sii``sii は止まらないので、ブレークポイントで止めて打ち切る
+        let mut debugger = Debugger::from(seq("```sii``sii"));
+        let produces = debugger.add_breakpoint(Breakpoint::Produces(term("Sxx")));
+        let steps = debugger.add_breakpoint(Breakpoint::Steps(100));
+        assert_eq!(debugger.resume(), Event::Break(vec![produces]));
+        assert_eq!(debugger.steps(), 0);
+        assert!(term("Sxx").matches(&Ast::from(debugger.stack().last().unwrap().clone())));
+        assert_eq!(
+            debugger.remove_breakpoint(produces),
+            Some(Breakpoint::Produces(term("Sxx")))
+        );
+        assert_eq!(debugger.resume(), Event::Break(vec![steps]));
+        assert_eq!(debugger.steps(), 100);
+        let rest = debugger.abort();
+        assert_eq!(rest.is_valid(), Ok(true));
+        assert_eq!(
+            Stax::from(rest).eval_with_fuel(1000),
+            Err(EvalError::OutOfFuel)
+        );
+    }
+}
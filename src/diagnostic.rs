@@ -0,0 +1,421 @@
+//! 入力中の位置を持つエラーと、その位置に印を付けた表示
+
+use std::fmt;
+
+use crate::error::{InvalidError, LexiconError, NotationError, StructureError};
+
+/// 入力中の位置。行と列は1から数え、列は文字単位
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct Span {
+    /// 先頭からのバイト数
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// `source` の `offset` バイト目の位置
+    pub fn at(source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    /// `source` の `point` 文字目の位置
+    pub fn at_char(source: &str, point: usize) -> Self {
+        let offset = source
+            .char_indices()
+            .nth(point)
+            .map_or(source.len(), |(offset, _)| offset);
+        Span::at(source, offset)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// 位置と、人が読むためのメッセージ
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// 問題のある行を表示し、その下の問題の位置に `^` を置く
+    ///
+    /// ```text
+    /// error: expected 2 more atoms after this application
+    ///  --> 1:2
+    ///   |
+    /// 1 | ``s
+    ///   |  ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let line = source.split('\n').nth(self.span.line - 1).unwrap_or("");
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        // タブはそのまま残して、端末での桁をそろえる
+        let indent: String = line
+            .chars()
+            .take(self.span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "error: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}^",
+            self.message, gutter, self.span, gutter, number, line, gutter, indent
+        )
+    }
+}
+
+/// エラーを、そのエラーが起きた入力の中に位置付ける
+pub trait Locate {
+    fn locate(&self, source: &str) -> Diagnostic;
+}
+
+impl Locate for LexiconError {
+    fn locate(&self, _source: &str) -> Diagnostic {
+        Diagnostic::new(
+            self.span(),
+            format!("unexpected character {:?}", self.error_char()),
+        )
+    }
+}
+
+impl Locate for StructureError {
+    fn locate(&self, _source: &str) -> Diagnostic {
+        Diagnostic::new(self.span(), self.message())
+    }
+}
+
+impl Locate for InvalidError {
+    fn locate(&self, source: &str) -> Diagnostic {
+        match self {
+            InvalidError::LexiconError(e) => e.locate(source),
+            InvalidError::FormulaError(e) => e.locate(source),
+        }
+    }
+}
+
+impl Locate for NotationError {
+    fn locate(&self, source: &str) -> Diagnostic {
+        let (point, message) = match self {
+            NotationError::UnexpectedChar(point, c) => {
+                (point, format!("unexpected character {:?}", c))
+            }
+            NotationError::UnclosedParen(point) => {
+                (point, "this parenthesis is never closed".to_string())
+            }
+            NotationError::UnmatchedParen(point) => {
+                (point, "no parenthesis to close here".to_string())
+            }
+            NotationError::EmptyTerm(point) => (point, "expected a term".to_string()),
+            NotationError::UnknownName(point, name) => (point, format!("unknown name {}", name)),
+        };
+        Diagnostic::new(Span::at_char(source, *point as usize), message)
+    }
+}
+
+/// Unlambda 記法の `source` を最後まで読み、字句と構造の誤りを全て位置の順に返す。
+/// 誤りがなければ空
+///
+/// 使えない文字は1文字ずつ報告し、構造はそれらを除いたトークンで調べる。
+/// 項が完成した後に残ったトークンは、その最初の位置にまとめて数を報告する。
+/// 閉じていない適用は、引数を欠くものそれぞれと、最も内側のものに足りない原子の数を報告する。
+pub fn validate(source: &str) -> Vec<Diagnostic> {
+    let scan = Scan::new(source);
+    let mut found: Vec<Diagnostic> = scan
+        .invalid
+        .iter()
+        .map(|&(offset, c)| {
+            Diagnostic::new(
+                Span::at(source, offset),
+                format!("unexpected character {:?}", c),
+            )
+        })
+        .collect();
+    if let Some((offset, count)) = scan.surplus {
+        let span = Span::at(source, offset);
+        found.push(StructureError::Surplus { span, count }.locate(source));
+    }
+    if let Some((innermost, outer)) = scan.open.split_last() {
+        // 関数を待つ外側の適用は、内側の適用が閉じれば閉じる
+        for &(offset, _) in outer.iter().filter(|(_, waiting)| *waiting == 2) {
+            found.push(Diagnostic::new(
+                Span::at(source, offset),
+                "this application is missing its argument",
+            ));
+        }
+        found.push(scan.missing(source, innermost.0).locate(source));
+    }
+    found.extend(scan.empty(source).map(|e| e.locate(source)));
+    found.sort_by_key(|diagnostic| diagnostic.span.offset);
+    found
+}
+
+/// Unlambda 記法の `source` を読み、構造の最初の誤りを位置とともに返す。
+/// `source` から読んだ列で `Ast::try_from` が失敗したときに使う
+pub(crate) fn structure_error(source: &str) -> StructureError {
+    let scan = Scan::new(source);
+    if let Some((offset, count)) = scan.surplus {
+        let span = Span::at(source, offset);
+        return StructureError::Surplus { span, count };
+    }
+    match scan.open.last() {
+        Some(&(offset, _)) => scan.missing(source, offset),
+        None => StructureError::Empty {
+            span: Span::at(source, source.len()),
+        },
+    }
+}
+
+// Unlambda 記法を1度読んだ結果。空白は読み飛ばす
+struct Scan {
+    // 使えない文字とその位置
+    invalid: Vec<(usize, char)>,
+    // 閉じていない適用の位置と、それが待っている項の数
+    open: Vec<(usize, usize)>,
+    complete: bool,
+    // 項が完成した後に残ったトークンの最初の位置と、残ったトークンの数
+    surplus: Option<(usize, usize)>,
+}
+
+impl Scan {
+    fn new(source: &str) -> Self {
+        let mut scan = Scan {
+            invalid: Vec::new(),
+            open: Vec::new(),
+            complete: false,
+            surplus: None,
+        };
+        for (offset, c) in source.char_indices() {
+            let atom = match c {
+                '`' => false,
+                's' | 'k' | 'i' => true,
+                c if c.is_whitespace() => continue,
+                c => {
+                    scan.invalid.push((offset, c));
+                    continue;
+                }
+            };
+            if scan.complete {
+                match &mut scan.surplus {
+                    Some((_, count)) => *count += 1,
+                    None => scan.surplus = Some((offset, 1)),
+                }
+                continue;
+            }
+            if !atom {
+                scan.open.push((offset, 2));
+                continue;
+            }
+            // 項が1つでき、それで閉じた適用もまた項になる
+            loop {
+                match scan.open.last_mut() {
+                    None => {
+                        scan.complete = true;
+                        break;
+                    }
+                    Some((_, waiting)) => {
+                        *waiting -= 1;
+                        if *waiting > 0 {
+                            break;
+                        }
+                        scan.open.pop();
+                    }
+                }
+            }
+        }
+        scan
+    }
+
+    // offset の適用の後に足りない原子の数
+    fn missing(&self, source: &str, offset: usize) -> StructureError {
+        // 外側の適用が待つ関数は、内側の閉じていない適用そのもの
+        let missing =
+            self.open.iter().map(|(_, waiting)| waiting).sum::<usize>() - (self.open.len() - 1);
+        StructureError::MissingAtoms {
+            span: Span::at(source, offset),
+            missing,
+        }
+    }
+
+    // トークンが1つもない
+    fn empty(&self, source: &str) -> Option<StructureError> {
+        if self.complete || !self.open.is_empty() {
+            return None;
+        }
+        Some(StructureError::Empty {
+            span: Span::at(source, source.len()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::notation::Applicative;
+    use std::convert::TryFrom;
+
+    fn unlambda(source: &str) -> Diagnostic {
+        Ast::parse_skipping_whitespace(source)
+            .unwrap_err()
+            .locate(source)
+    }
+
+    #[test]
+    fn spans() {
+        let source = "`s\n\tλk\ni";
+        assert_eq!(
+            Span::at(source, 0),
+            Span {
+                offset: 0,
+                line: 1,
+                column: 1
+            }
+        );
+        // λ は2バイト
+        assert_eq!(
+            Span::at(source, 6),
+            Span {
+                offset: 6,
+                line: 2,
+                column: 3
+            }
+        );
+        assert_eq!(Span::at_char(source, 5), Span::at(source, 6));
+        assert_eq!(Span::at_char(source, 100), Span::at(source, source.len()));
+        assert_eq!(Span::at(source, source.len()).to_string(), "3:2");
+    }
+
+    #[test]
+    fn unlambda_errors() {
+        let found = unlambda("``s");
+        assert_eq!(found.span, Span::at("``s", 1));
+        assert_eq!(
+            found.message,
+            "expected 2 more atoms after this application"
+        );
+        assert_eq!(
+            unlambda("`s").message,
+            "expected 1 more atom after this application"
+        );
+        assert_eq!(unlambda("").message, "expected a term");
+
+        let found = unlambda("`sk\n  i");
+        assert_eq!(found.span.to_string(), "2:3");
+        assert_eq!(found.message, "1 surplus token after the end of the term");
+
+        let found = unlambda("``s\n`k?i");
+        assert_eq!(found.span.to_string(), "2:3");
+        assert_eq!(found.message, "unexpected character '?'");
+
+        // 位置はエラーの値が持っている
+        assert_eq!(
+            Ast::try_from("``s"),
+            Err(InvalidError::FormulaError(StructureError::MissingAtoms {
+                span: Span::at("``s", 1),
+                missing: 2
+            }))
+        );
+        let e = Ast::parse_skipping_whitespace("`sk\n  i").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "1 surplus token after the end of the term at 2:3"
+        );
+        // 既定では空白を読み飛ばさない
+        let e = Ast::try_from("`s k").unwrap_err();
+        assert_eq!(e.to_string(), "unexpected character ' ' at 1:3");
+    }
+
+    #[test]
+    fn notation_errors() {
+        let source = "S(K\n  S";
+        let found = Applicative::try_from(source).unwrap_err().locate(source);
+        assert_eq!(found.span.to_string(), "1:2");
+        assert_eq!(found.message, "this parenthesis is never closed");
+
+        let source = "SK\n  x";
+        let found = Applicative::try_from(source).unwrap_err().locate(source);
+        assert_eq!(found.span.to_string(), "2:3");
+    }
+
+    #[test]
+    fn render() {
+        assert_eq!(
+            unlambda("``s").render("``s"),
+            "error: expected 2 more atoms after this application\n --> 1:2\n  |\n1 | ``s\n  |  ^"
+        );
+        let source = "`s\n\t`k?\n";
+        assert_eq!(
+            unlambda(source).render(source),
+            "error: unexpected character '?'\n --> 2:4\n  |\n2 | \t`k?\n  | \t  ^"
+        );
+        // CRLF の行
+        let source = "``sk\r\n";
+        assert_eq!(
+            unlambda(source).render(source),
+            "error: expected 1 more atom after this application\n --> 1:1\n  |\n1 | ``sk\n  | ^"
+        );
+    }
+
+    #[test]
+    fn all_errors() {
+        assert_eq!(validate("``s`kii"), vec![]);
+        assert_eq!(validate("  s\n"), vec![]);
+
+        let source = "`?s\n-kk`i";
+        let found: Vec<String> = validate(source).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            found,
+            vec![
+                "1:2: unexpected character '?'",
+                "2:1: unexpected character '-'",
+                "2:3: 3 surplus tokens after the end of the term",
+            ]
+        );
+
+        // `s の引数になる適用の他は、外側の適用がそれぞれ引数を欠く
+        let source = "``x`s``";
+        let found: Vec<String> = validate(source).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            found,
+            vec![
+                "1:1: this application is missing its argument",
+                "1:2: this application is missing its argument",
+                "1:3: unexpected character 'x'",
+                "1:6: this application is missing its argument",
+                "1:7: expected 5 more atoms after this application",
+            ]
+        );
+
+        let found: Vec<String> = validate(" ?").iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            found,
+            vec!["1:2: unexpected character '?'", "1:3: expected a term"]
+        );
+    }
+}
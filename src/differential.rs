@@ -0,0 +1,293 @@
+//! 複数の評価器に同じ項を評価させて結果を突き合わせる、テスト用の仕組み
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::ast::{Ast, AstInner};
+use crate::enumerate;
+use crate::error::EvalError;
+use crate::notation::Applicative;
+use crate::random::{random_term, Distribution};
+use crate::sequence::Sequence;
+use crate::stack::Stax;
+use crate::term::Ski;
+use crate::token::Atom;
+
+/// 評価の結果
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Outcome {
+    Normal(Ast),
+    OutOfFuel,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Normal(ast) => write!(f, "{}", Applicative::from(ast.clone())),
+            Outcome::OutOfFuel => write!(f, "OutOfFuel"),
+        }
+    }
+}
+
+/// 突き合わせの対象になる評価器
+pub trait Evaluator {
+    fn name(&self) -> &str;
+
+    /// `ast` を規則の適用 `fuel` 回までで評価する
+    fn evaluate(&self, ast: &Ast, fuel: u64) -> Outcome;
+}
+
+/// `Stax` による評価
+pub struct StaxEvaluator;
+
+impl Evaluator for StaxEvaluator {
+    fn name(&self) -> &str {
+        "stax"
+    }
+
+    fn evaluate(&self, ast: &Ast, fuel: u64) -> Outcome {
+        let mut stax = Stax::from(Sequence::from(ast.clone()));
+        match stax.eval_with_fuel(fuel) {
+            Ok(seq) => Outcome::Normal(Ast::try_from(seq).unwrap()),
+            Err(EvalError::OutOfFuel) => Outcome::OutOfFuel,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+/// 葉から順に `Ski::apply` を畳み込む評価
+pub struct SkiEvaluator;
+
+impl Evaluator for SkiEvaluator {
+    fn name(&self) -> &str {
+        "ski"
+    }
+
+    fn evaluate(&self, ast: &Ast, fuel: u64) -> Outcome {
+        enum Frame<'a> {
+            Visit(&'a Ast),
+            Build,
+        }
+
+        let mut fuel = fuel;
+        let mut rest = vec![Frame::Visit(ast)];
+        let mut done: Vec<Ski> = Vec::new();
+        while let Some(frame) = rest.pop() {
+            match frame {
+                Frame::Visit(Ast::Leaf(atom)) => done.push(Ski::from(*atom)),
+                Frame::Visit(Ast::Apply(inner)) => {
+                    rest.push(Frame::Build);
+                    rest.push(Frame::Visit(inner.argument()));
+                    rest.push(Frame::Visit(inner.function()));
+                }
+                Frame::Build => {
+                    let argument = done.pop().unwrap();
+                    let function = done.pop().unwrap();
+                    match function.apply_with_fuel(argument, &mut fuel) {
+                        Some(ski) => done.push(ski),
+                        None => return Outcome::OutOfFuel,
+                    }
+                }
+            }
+        }
+        Outcome::Normal(Ast::from(done.pop().unwrap()))
+    }
+}
+
+/// 評価器の間で結果が食い違った項
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Counterexample {
+    /// 最初に見つかった項
+    pub original: Ast,
+    /// 食い違いを保ったまま小さくした項
+    pub term: Ast,
+    /// `term` に対する各評価器の結果
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", Applicative::from(self.term.clone()))?;
+        for (name, outcome) in &self.outcomes {
+            writeln!(f, "    {}: {}", name, outcome)?;
+        }
+        write!(
+            f,
+            "(shrunk from {})",
+            Applicative::from(self.original.clone())
+        )
+    }
+}
+
+/// 登録した評価器を同じ `fuel` で走らせて突き合わせる
+pub struct Harness {
+    evaluators: Vec<Box<dyn Evaluator>>,
+    fuel: u64,
+}
+
+impl Harness {
+    /// 評価器を1つも持たない
+    pub fn new(fuel: u64) -> Self {
+        Harness {
+            evaluators: Vec::new(),
+            fuel,
+        }
+    }
+
+    /// `StaxEvaluator` と `SkiEvaluator` を登録済み
+    pub fn standard(fuel: u64) -> Self {
+        let mut harness = Harness::new(fuel);
+        harness.register(StaxEvaluator);
+        harness.register(SkiEvaluator);
+        harness
+    }
+
+    pub fn register(&mut self, evaluator: impl Evaluator + 'static) {
+        self.evaluators.push(Box::new(evaluator));
+    }
+
+    pub fn run(&self, ast: &Ast) -> Vec<(String, Outcome)> {
+        self.evaluators
+            .iter()
+            .map(|e| (e.name().to_string(), e.evaluate(ast, self.fuel)))
+            .collect()
+    }
+
+    fn disagrees(&self, ast: &Ast) -> bool {
+        let outcomes = self.run(ast);
+        outcomes.windows(2).any(|pair| pair[0].1 != pair[1].1)
+    }
+
+    /// 結果が食い違えば、食い違いが残る範囲で項を小さくして返す
+    pub fn check(&self, ast: &Ast) -> Result<(), Counterexample> {
+        if !self.disagrees(ast) {
+            return Ok(());
+        }
+        let mut term = ast.clone();
+        while let Some(smaller) = shrink(&term).into_iter().find(|t| self.disagrees(t)) {
+            term = smaller;
+        }
+        Err(Counterexample {
+            original: ast.clone(),
+            outcomes: self.run(&term),
+            term,
+        })
+    }
+
+    /// 葉の数が `max_leaves` 以下の全ての項を調べる。調べた項の数を返す
+    pub fn check_enumerated(&self, max_leaves: usize) -> Result<usize, Counterexample> {
+        let mut checked = 0;
+        for leaves in 1..=max_leaves {
+            for ast in enumerate::terms(leaves) {
+                self.check(&ast)?;
+                checked += 1;
+            }
+        }
+        Ok(checked)
+    }
+
+    /// シード `seed` から `seed + count - 1` までの乱択の項を調べる
+    pub fn check_random(
+        &self,
+        seed: u64,
+        count: u64,
+        size: usize,
+        distribution: Distribution,
+    ) -> Result<u64, Counterexample> {
+        for s in seed..seed + count {
+            self.check(&random_term(s, size, distribution))?;
+        }
+        Ok(count)
+    }
+}
+
+// ast より小さい候補。部分項を子や原子で置き換えたもの
+fn shrink(ast: &Ast) -> Vec<Ast> {
+    const ATOMS: [Atom; 3] = [Atom::S, Atom::K, Atom::I];
+
+    let mut found = Vec::new();
+    match ast {
+        Ast::Leaf(atom) => {
+            for smaller in ATOMS.iter().take_while(|a| *a != atom) {
+                found.push(Ast::Leaf(*smaller));
+            }
+        }
+        Ast::Apply(inner) => {
+            found.extend(ATOMS.iter().map(|a| Ast::Leaf(*a)));
+            found.push(inner.to_function());
+            found.push(inner.to_argument());
+            for function in shrink(inner.function()) {
+                found.push(Ast::Apply(AstInner::new(function, inner.to_argument())));
+            }
+            for argument in shrink(inner.argument()) {
+                found.push(Ast::Apply(AstInner::new(inner.to_function(), argument)));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn evaluators() {
+        let term = ast("```sski");
+        let target = Outcome::Normal(ast("``si`ki"));
+        assert_eq!(StaxEvaluator.evaluate(&term, 10), target);
+        assert_eq!(SkiEvaluator.evaluate(&term, 10), target);
+        let omega = ast("```sii``sii");
+        assert_eq!(StaxEvaluator.evaluate(&omega, 100), Outcome::OutOfFuel);
+        assert_eq!(SkiEvaluator.evaluate(&omega, 100), Outcome::OutOfFuel);
+    }
+
+    #[test]
+    fn standard_agree() {
+        let harness = Harness::standard(100);
+        assert_eq!(harness.check_enumerated(5), Ok(3 + 9 + 54 + 405 + 3402));
+        for distribution in &[
+            Distribution::Uniform,
+            Distribution::Deep,
+            Distribution::Wide,
+        ] {
+            assert_eq!(harness.check_random(0, 100, 30, *distribution), Ok(100));
+        }
+    }
+
+    // 何も簡約しない、誤った評価器
+    struct Identity;
+
+    impl Evaluator for Identity {
+        fn name(&self) -> &str {
+            "identity"
+        }
+
+        fn evaluate(&self, ast: &Ast, _fuel: u64) -> Outcome {
+            Outcome::Normal(ast.clone())
+        }
+    }
+
+    #[test]
+    fn shrinks_counterexample() {
+        let mut harness = Harness::standard(100);
+        harness.register(Identity);
+        let original = ast("``s`k`si``k`ii`ss");
+        let found = harness.check(&original).unwrap_err();
+        assert_eq!(found.original, original);
+        assert_eq!(found.term, ast("`is"));
+        assert_eq!(
+            found.outcomes,
+            vec![
+                ("stax".to_string(), Outcome::Normal(ast("s"))),
+                ("ski".to_string(), Outcome::Normal(ast("s"))),
+                ("identity".to_string(), Outcome::Normal(ast("`is"))),
+            ]
+        );
+        assert!(found.to_string().starts_with("IS\n    stax: S\n"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Graphviz DOT 形式への書き出し
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ast::Ast;
+use crate::graph::{self, Limits};
+use crate::notation::Applicative;
+use crate::reduce;
+use crate::term::Ski;
+use crate::token::Atom;
+
+/// 適用ノードを `@`、原子コンビネータを葉とする木として書き出す
+pub fn ast(ast: &Ast) -> String {
+    Tree::new(false).write(ast)
+}
+
+/// 構造が等しい部分項を1つのノードにまとめ、DAG として書き出す
+pub fn ast_shared(ast: &Ast) -> String {
+    Tree::new(true).write(ast)
+}
+
+/// `Sp(x)` を `` `sx ``、`Spp(x, y)` を ``` ``sxy ``` の適用ノードとして書き出す
+pub fn ski(ski: &Ski) -> String {
+    self::ast(&Ast::from(ski.clone()))
+}
+
+pub fn ski_shared(ski: &Ski) -> String {
+    ast_shared(&Ast::from(ski.clone()))
+}
+
+/// `start` から1ステップ簡約で到達できる項を、大きさ (葉の数) が `max_size` 以下のものに限って全て書き出す。
+/// 辺のラベルは発火した規則
+pub fn reduction_graph(start: &Ast, max_size: usize) -> String {
+    let graph = graph::explore(
+        start,
+        Limits {
+            max_size,
+            max_depth: usize::MAX,
+            max_nodes: usize::MAX,
+        },
+    );
+
+    let mut out = String::from("digraph reduction {\n    node [shape=box];\n");
+    for (id, term) in graph.nodes.into_iter().enumerate() {
+        let normal = reduce::redexes(&term).is_empty();
+        let _ = writeln!(
+            out,
+            "    n{} [label=\"{}\"{}];",
+            id,
+            Applicative::from(term),
+            if normal { ", peripheries=2" } else { "" }
+        );
+    }
+    for edge in graph.edges {
+        let _ = writeln!(
+            out,
+            "    n{} -> n{} [label=\"{}\"];",
+            edge.from, edge.to, edge.redex.rule
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+enum Node {
+    Leaf(Atom),
+    Apply(usize, usize),
+}
+
+struct Tree {
+    shared: bool,
+    ids: HashMap<Node, usize>,
+    out: String,
+    next: usize,
+}
+
+impl Tree {
+    fn new(shared: bool) -> Self {
+        Tree {
+            shared,
+            ids: HashMap::new(),
+            out: String::from("digraph term {\n    node [shape=plaintext];\n"),
+            next: 0,
+        }
+    }
+
+    fn write(mut self, ast: &Ast) -> String {
+        // 帰り掛けに子のノード番号が揃ってから親を出力する
+        let mut rest = vec![(ast, false)];
+        let mut done = Vec::new();
+        while let Some((ast, visited)) = rest.pop() {
+            match ast {
+                Ast::Leaf(atom) => {
+                    let id = self.node(Node::Leaf(*atom));
+                    done.push(id);
+                }
+                Ast::Apply(inner) if !visited => {
+                    rest.push((ast, true));
+                    rest.push((inner.argument(), false));
+                    rest.push((inner.function(), false));
+                }
+                Ast::Apply(_) => {
+                    let argument = done.pop().unwrap();
+                    let function = done.pop().unwrap();
+                    let id = self.node(Node::Apply(function, argument));
+                    done.push(id);
+                }
+            }
+        }
+        self.out.push_str("}\n");
+        self.out
+    }
+
+    fn node(&mut self, node: Node) -> usize {
+        if self.shared {
+            if let Some(&id) = self.ids.get(&node) {
+                return id;
+            }
+        }
+        let id = self.next;
+        self.next += 1;
+        match node {
+            Node::Leaf(atom) => {
+                let _ = writeln!(
+                    self.out,
+                    "    n{} [label=\"{}\"];",
+                    id,
+                    String::from(atom).to_uppercase()
+                );
+            }
+            Node::Apply(function, argument) => {
+                let _ = writeln!(self.out, "    n{} [label=\"@\", shape=circle];", id);
+                let _ = writeln!(self.out, "    n{} -> n{} [tailport=sw];", id, function);
+                let _ = writeln!(self.out, "    n{} -> n{} [tailport=se];", id, argument);
+            }
+        }
+        if self.shared {
+            self.ids.insert(node, id);
+        }
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn tree() {
+        let term = Ast::try_from("``sii").unwrap();
+        let target = "digraph term {
+    node [shape=plaintext];
+    n0 [label=\"S\"];
+    n1 [label=\"I\"];
+    n2 [label=\"@\", shape=circle];
+    n2 -> n0 [tailport=sw];
+    n2 -> n1 [tailport=se];
+    n3 [label=\"I\"];
+    n4 [label=\"@\", shape=circle];
+    n4 -> n2 [tailport=sw];
+    n4 -> n3 [tailport=se];
+}
+";
+        assert_eq!(ast(&term), target);
+    }
+
+    #[test]
+    fn shared() {
+        // ```sii``sii: ``sii は1つのノードにまとまる
+        let term = Ast::try_from("```sii``sii").unwrap();
+        let dot = ast_shared(&term);
+        assert_eq!(dot.matches("label=\"I\"").count(), 1);
+        assert_eq!(dot.matches("label=\"@\"").count(), 3);
+        assert_eq!(ast(&term).matches("label=\"@\"").count(), 5);
+    }
+
+    #[test]
+    fn ski_tree() {
+        let term = Ski::from(Atom::K);
+        assert!(ski(&term).contains("n0 [label=\"K\"];"));
+    }
+
+    #[test]
+    fn reduction() {
+        // ``k`ii`is は3通りに簡約でき、どの経路でも i に至る
+        let term = Ast::try_from("``k`ii`is").unwrap();
+        let dot = reduction_graph(&term, 10);
+        let edges = dot.lines().filter(|l| l.contains(" -> ")).count();
+        let nodes = dot.lines().filter(|l| l.contains("[label=")).count() - edges;
+        assert_eq!((nodes, edges), (6, 9));
+        assert!(dot.contains("[label=\"I\", peripheries=2]"));
+        assert!(dot.contains("n0 -> n1 [label=\"K\"];"));
+    }
+}
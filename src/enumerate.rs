@@ -0,0 +1,229 @@
+//! 大きさを決めた well-formed な項の全列挙
+//!
+//! 葉の数が `n` の項は、関数側の葉の数、関数側の順位、引数側の順位の辞書式順に並べる。
+//! 葉の数が1の項は `s`, `k`, `i` の順。`rank` と `unrank` はこの順序での番号との全単射になる。
+
+use std::convert::TryFrom;
+
+use crate::ast::{Ast, AstInner};
+use crate::sequence::Sequence;
+use crate::token::Atom;
+
+const ATOMS: [Atom; 3] = [Atom::S, Atom::K, Atom::I];
+
+/// 葉の数がちょうど `leaves` の項の個数。Catalan数 C(leaves - 1) と 3^leaves の積。
+/// `u128` に収まらなければ `None`
+pub fn count(leaves: usize) -> Option<u128> {
+    if leaves == 0 {
+        return Some(0);
+    }
+    // C(m + 1) = C(m) * 2(2m + 1) / (m + 2)
+    let mut catalan: u128 = 1;
+    for m in 0..(leaves as u128 - 1) {
+        catalan = catalan.checked_mul(2 * (2 * m + 1))? / (m + 2);
+    }
+    catalan.checked_mul(3u128.checked_pow(leaves as u32)?)
+}
+
+/// 長さ `len` で `Sequence::is_valid` を満たす列の個数
+pub fn count_sequences(len: usize) -> Option<u128> {
+    count(leaves_of(len))
+}
+
+// 長さ len の well-formed な列の葉の数。そのような列がなければ0
+fn leaves_of(len: usize) -> usize {
+    match len % 2 {
+        0 => 0,
+        _ => len.div_ceil(2),
+    }
+}
+
+// counts[n] == count(n)
+fn counts(leaves: usize) -> Option<Vec<u128>> {
+    (0..=leaves).map(count).collect()
+}
+
+// 関数側の葉の数が left 未満の項の個数
+fn offset(counts: &[u128], leaves: usize, left: usize) -> u128 {
+    (1..left).map(|k| counts[k] * counts[leaves - k]).sum()
+}
+
+/// 同じ葉の数の項の中での順位
+pub fn rank(ast: &Ast) -> Option<u128> {
+    let counts = counts(ast.size())?;
+    Some(rank_with(ast, &counts))
+}
+
+fn rank_with(ast: &Ast, counts: &[u128]) -> u128 {
+    match ast {
+        Ast::Leaf(atom) => ATOMS.iter().position(|a| a == atom).unwrap() as u128,
+        Ast::Apply(inner) => {
+            let (function, argument) = (inner.function(), inner.argument());
+            let left = function.size();
+            let leaves = left + argument.size();
+            offset(counts, leaves, left)
+                + rank_with(function, counts) * counts[leaves - left]
+                + rank_with(argument, counts)
+        }
+    }
+}
+
+/// 葉の数が `leaves` の項のうち、順位が `index` のもの。範囲外ならば `None`
+pub fn unrank(leaves: usize, index: u128) -> Option<Ast> {
+    let counts = counts(leaves)?;
+    if index >= counts[leaves] {
+        return None;
+    }
+    Some(unrank_with(leaves, index, &counts))
+}
+
+fn unrank_with(leaves: usize, mut index: u128, counts: &[u128]) -> Ast {
+    if leaves == 1 {
+        return Ast::Leaf(ATOMS[index as usize]);
+    }
+    let mut left = 1;
+    loop {
+        let block = counts[left] * counts[leaves - left];
+        if index < block {
+            break;
+        }
+        index -= block;
+        left += 1;
+    }
+    let right = counts[leaves - left];
+    Ast::Apply(AstInner::new(
+        unrank_with(left, index / right, counts),
+        unrank_with(leaves - left, index % right, counts),
+    ))
+}
+
+/// 葉の数が `leaves` の項を順位の順に全て返す
+///
+/// # Panics
+///
+/// 項の個数 `count(leaves)` が `u128` に収まらないとき (`leaves` が39以上)。
+/// 先に `count` で確かめれば避けられる
+pub fn terms(leaves: usize) -> Terms {
+    let counts = counts(leaves).expect("too many terms to enumerate");
+    Terms {
+        leaves,
+        index: 0,
+        counts,
+    }
+}
+
+pub struct Terms {
+    leaves: usize,
+    index: u128,
+    counts: Vec<u128>,
+}
+
+impl Iterator for Terms {
+    type Item = Ast;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.counts[self.leaves] {
+            return None;
+        }
+        let ast = unrank_with(self.leaves, self.index, &self.counts);
+        self.index += 1;
+        Some(ast)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let rest = self.counts[self.leaves] - self.index;
+        match usize::try_from(rest) {
+            Ok(rest) => (rest, Some(rest)),
+            Err(_) => (usize::MAX, None),
+        }
+    }
+}
+
+/// 長さ `len` で `Sequence::is_valid` を満たす列を、対応する項の順位の順に全て返す
+///
+/// # Panics
+///
+/// `terms` と同じく、列の個数 `count_sequences(len)` が `u128` に収まらないとき
+pub fn sequences(len: usize) -> Sequences {
+    Sequences(terms(leaves_of(len)))
+}
+
+pub struct Sequences(Terms);
+
+impl Iterator for Sequences {
+    type Item = Sequence;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Sequence::from)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn counting() {
+        let catalan = [1, 1, 2, 5, 14, 42, 132];
+        for (n, c) in catalan.iter().enumerate() {
+            assert_eq!(count(n + 1), Some(c * 3u128.pow(n as u32 + 1)));
+        }
+        assert_eq!(count(0), Some(0));
+        assert_eq!(count_sequences(5), Some(2 * 27));
+        assert_eq!(count_sequences(4), Some(0));
+        assert_eq!(count(1000), None);
+    }
+
+    #[test]
+    fn enumeration() {
+        for leaves in 1..=5 {
+            let all: Vec<Ast> = terms(leaves).collect();
+            assert_eq!(all.len() as u128, count(leaves).unwrap());
+            let distinct: HashSet<&Ast> = all.iter().collect();
+            assert_eq!(distinct.len(), all.len());
+            assert!(all.iter().all(|ast| ast.size() == leaves));
+        }
+        let first: Vec<Ast> = terms(2).take(2).collect();
+        assert_eq!(
+            first,
+            vec![Ast::try_from("`ss").unwrap(), Ast::try_from("`sk").unwrap()]
+        );
+    }
+
+    #[test]
+    fn sequence_enumeration() {
+        let all: Vec<Sequence> = sequences(5).collect();
+        assert_eq!(all.len(), 54);
+        assert!(all
+            .iter()
+            .all(|seq| seq.len() == 5 && seq.is_valid() == Ok(true)));
+        assert_eq!(sequences(4).count(), 0);
+        assert_eq!(sequences(0).count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many terms to enumerate")]
+    fn too_many_terms() {
+        assert!(count(38).is_some());
+        assert_eq!(count(39), None);
+        terms(39);
+    }
+
+    #[test]
+    fn ranking() {
+        for leaves in 1..=4 {
+            for (index, ast) in terms(leaves).enumerate() {
+                assert_eq!(rank(&ast), Some(index as u128));
+                assert_eq!(unrank(leaves, index as u128), Some(ast));
+            }
+        }
+        assert_eq!(unrank(2, 9), None);
+        let ast = Ast::try_from("```sii``sii").unwrap();
+        assert_eq!(unrank(6, rank(&ast).unwrap()), Some(ast));
+    }
+}
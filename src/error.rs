@@ -1,26 +1,39 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::diagnostic::Span;
+use crate::sequence::Sequence;
+
+/// Unlambda 記法で使えない文字と、その位置
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub struct LexiconError {
-    error_point: u32,
+    span: Span,
     error_char: char,
 }
 
 impl fmt::Display for LexiconError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}, {}", self.error_point, self.error_char)
+        write!(
+            f,
+            "unexpected character {:?} at {}",
+            self.error_char, self.span
+        )
     }
 }
 
 impl Error for LexiconError {}
 
 impl LexiconError {
-    pub fn new(error_point: u32, error_char: char) -> Self {
-        LexiconError {
-            error_point,
-            error_char,
-        }
+    pub fn new(span: Span, error_char: char) -> Self {
+        LexiconError { span, error_char }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn error_char(&self) -> char {
+        self.error_char
     }
 }
 
@@ -32,16 +45,103 @@ pub enum FormulaError {
 
 impl fmt::Display for FormulaError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::NotEnoughAtoms => write!(f, "not enough atoms for the applications"),
+            Self::SurplusTokens => write!(f, "surplus tokens after a complete term"),
+        }
     }
 }
 
 impl Error for FormulaError {}
 
+/// Unlambda 記法の入力の構造のエラー (`FormulaError`) と、その位置
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum StructureError {
+    /// トークンが1つもない。位置は入力の終わり
+    Empty { span: Span },
+    /// `span` の適用を閉じるのに原子が `missing` 個足りない。位置は最も内側の閉じていない適用
+    MissingAtoms { span: Span, missing: usize },
+    /// 項の後に `count` 個のトークンが余った。位置は最初に余ったトークン
+    Surplus { span: Span, count: usize },
+}
+
+impl fmt::Display for StructureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span())
+    }
+}
+
+impl Error for StructureError {}
+
+impl StructureError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Empty { span } | Self::MissingAtoms { span, .. } | Self::Surplus { span, .. } => {
+                *span
+            }
+        }
+    }
+
+    /// 位置を含まないメッセージ
+    pub fn message(&self) -> String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match self {
+            Self::Empty { .. } => "expected a term".to_string(),
+            Self::MissingAtoms { missing, .. } => format!(
+                "expected {} more atom{} after this application",
+                missing,
+                plural(*missing)
+            ),
+            Self::Surplus { count, .. } => format!(
+                "{} surplus token{} after the end of the term",
+                count,
+                plural(*count)
+            ),
+        }
+    }
+
+    /// 位置を除いた分類
+    pub fn kind(&self) -> FormulaError {
+        match self {
+            Self::Empty { .. } | Self::MissingAtoms { .. } => FormulaError::NotEnoughAtoms,
+            Self::Surplus { .. } => FormulaError::SurplusTokens,
+        }
+    }
+}
+
+/// 関数適用記法 (`S(KS)K`) の構文エラー。位置は文字単位
+#[derive(Debug, Eq, PartialEq, Hash)]
+pub enum NotationError {
+    UnexpectedChar(u32, char),
+    UnclosedParen(u32),
+    UnmatchedParen(u32),
+    EmptyTerm(u32),
+    UnknownName(u32, String),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar(point, c) => {
+                write!(f, "unexpected character {:?} at {}", c, point)
+            }
+            Self::UnclosedParen(point) => write!(f, "unclosed parenthesis at {}", point),
+            Self::UnmatchedParen(point) => {
+                write!(f, "unmatched closing parenthesis at {}", point)
+            }
+            Self::EmptyTerm(point) => write!(f, "expected a term at {}", point),
+            Self::UnknownName(point, name) => write!(f, "unknown name {} at {}", name, point),
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+/// Unlambda 記法の入力の誤り。どちらも入力の中の位置を持つ
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub enum InvalidError {
     LexiconError(LexiconError),
-    FormulaError(FormulaError),
+    FormulaError(StructureError),
 }
 
 impl fmt::Display for InvalidError {
@@ -61,8 +161,85 @@ impl From<LexiconError> for InvalidError {
     }
 }
 
-impl From<FormulaError> for InvalidError {
-    fn from(e: FormulaError) -> Self {
+impl From<StructureError> for InvalidError {
+    fn from(e: StructureError) -> Self {
         InvalidError::FormulaError(e)
     }
 }
+
+/// 評価の失敗
+#[derive(Debug, Eq, PartialEq, Hash)]
+pub enum EvalError {
+    FormulaError(FormulaError),
+    /// 決められた回数の規則の適用で評価が終わらなかった
+    OutOfFuel,
+    /// `cycle_length` 回の規則の適用ごとに同じ状態に戻る
+    Diverges {
+        cycle_length: u64,
+    },
+    /// 項の大きさ (原子の数) が `initial` から `size` まで増えた
+    Grows {
+        initial: usize,
+        size: usize,
+    },
+    /// 途中の項の節点 (適用と原子) の数が `limit` を超える
+    TooManyNodes {
+        limit: usize,
+    },
+    /// `Stax` のスタックの長さが `limit` を超えた
+    StackTooDeep {
+        limit: usize,
+    },
+    /// 作った節点の数の合計が `limit` を超える
+    TooManyAllocations {
+        limit: u64,
+    },
+    /// `CancelToken` で止められた。`state` はそこまで評価した項
+    Cancelled {
+        steps: u64,
+        state: Sequence,
+    },
+    /// 期限を過ぎた。`state` はそこまで評価した項
+    TimedOut {
+        steps: u64,
+        state: Sequence,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FormulaError(err) => err.fmt(f),
+            Self::OutOfFuel => write!(f, "out of fuel"),
+            Self::Diverges { cycle_length } => {
+                write!(
+                    f,
+                    "diverges: the state repeats every {} steps",
+                    cycle_length
+                )
+            }
+            Self::Grows { initial, size } => {
+                write!(f, "the term grew from {} to {} atoms", initial, size)
+            }
+            Self::TooManyNodes { limit } => {
+                write!(f, "the term would have more than {} nodes", limit)
+            }
+            Self::StackTooDeep { limit } => {
+                write!(f, "the stack grew deeper than {}", limit)
+            }
+            Self::TooManyAllocations { limit } => {
+                write!(f, "more than {} nodes would be allocated", limit)
+            }
+            Self::Cancelled { steps, .. } => write!(f, "cancelled after {} steps", steps),
+            Self::TimedOut { steps, .. } => write!(f, "timed out after {} steps", steps),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+impl From<FormulaError> for EvalError {
+    fn from(e: FormulaError) -> Self {
+        EvalError::FormulaError(e)
+    }
+}
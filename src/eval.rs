@@ -0,0 +1,317 @@
+//! 評価の設定と、停止しない評価の検出
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::ast::Ast;
+use crate::error::EvalError;
+use crate::reduce::{self, Strategy};
+use crate::sequence::Sequence;
+
+/// 別のスレッドから評価を止めるための旗。複製したものは同じ旗を共有する
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 評価を止める。評価は次に旗を確かめたところで `EvalError::Cancelled` を返す
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// 同じ旗を共有しているときに等しい
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancelToken {}
+
+impl Hash for CancelToken {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state)
+    }
+}
+
+/// 評価の設定。既定では何も制限しない
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Default)]
+pub struct Config {
+    /// 規則の適用回数の上限
+    pub fuel: Option<u64>,
+    /// 評価の状態が前と同じになったら `EvalError::Diverges` にする
+    pub detect_loops: bool,
+    /// 項の大きさ (原子の数) が始めの何倍を超えたら `EvalError::Grows` にするか
+    pub max_growth: Option<usize>,
+    /// 途中の項の節点 (適用と原子) の数の上限。超えると `EvalError::TooManyNodes`
+    pub max_nodes: Option<usize>,
+    /// `Stax` のスタックの長さの上限。超えると `EvalError::StackTooDeep`
+    pub max_stack: Option<usize>,
+    /// 作る節点 (適用と原子) の数の合計の上限。超えると `EvalError::TooManyAllocations`。
+    /// `Stax` は S の規則で複製した引数と部分適用の節点を、`normalize` は書き換えるたびに作り直す項全体を数える
+    pub max_allocations: Option<u64>,
+    /// 旗が立ったら `EvalError::Cancelled` にする
+    pub cancel: Option<CancelToken>,
+    /// この時刻を過ぎたら `EvalError::TimedOut` にする
+    pub deadline: Option<Instant>,
+}
+
+impl Config {
+    /// 規則の適用回数だけを制限する設定
+    pub fn with_fuel(fuel: u64) -> Self {
+        Config {
+            fuel: Some(fuel),
+            ..Config::default()
+        }
+    }
+
+    pub(crate) fn out_of_fuel(&self, steps: u64) -> bool {
+        self.fuel.is_some_and(|fuel| steps >= fuel)
+    }
+
+    pub(crate) fn check_growth(&self, initial: usize, size: usize) -> Result<(), EvalError> {
+        match self.max_growth {
+            Some(factor) if size > initial.saturating_mul(factor) => {
+                Err(EvalError::Grows { initial, size })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_nodes(&self, nodes: usize) -> Result<(), EvalError> {
+        match self.max_nodes {
+            Some(limit) if nodes > limit => Err(EvalError::TooManyNodes { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// 止める指示か期限があるか
+    pub(crate) fn interruptible(&self) -> bool {
+        self.cancel.is_some() || self.deadline.is_some()
+    }
+
+    /// 止める指示か期限の超過があれば、それまでの規則の適用回数と途中の項を付けたエラーにする
+    pub(crate) fn check_interrupt(
+        &self,
+        steps: u64,
+        state: impl FnOnce() -> Sequence,
+    ) -> Result<(), EvalError> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(EvalError::Cancelled {
+                steps,
+                state: state(),
+            });
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(EvalError::TimedOut {
+                steps,
+                state: state(),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_allocations(&self, allocations: u64) -> Result<(), EvalError> {
+        match self.max_allocations {
+            Some(limit) if allocations > limit => Err(EvalError::TooManyAllocations { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 決定的に進む状態の列から周期を見つける (Brent の方法)。
+///
+/// 覚えておく状態は1つだけで、2の冪ごとに置き換える。
+/// ハッシュ値で先に比べ、等しいときだけ状態そのものを比べる
+pub(crate) struct Cycle<T> {
+    saved: Option<(u64, T)>,
+    power: u64,
+    length: u64,
+}
+
+impl<T: Hash + Eq + Clone> Cycle<T> {
+    pub(crate) fn new() -> Self {
+        Cycle {
+            saved: None,
+            power: 1,
+            length: 0,
+        }
+    }
+
+    /// 状態が1つ進むごとに呼ぶ。覚えている状態に戻っていれば周期の長さを返す
+    pub(crate) fn observe(&mut self, state: &T) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        state.hash(&mut hasher);
+        let hash = hasher.finish();
+        match &self.saved {
+            Some((saved_hash, saved)) if *saved_hash == hash && saved == state => {
+                return Some(self.length)
+            }
+            Some(_) if self.length < self.power => {}
+            Some(_) => {
+                self.saved = Some((hash, state.clone()));
+                self.power *= 2;
+                self.length = 0;
+            }
+            None => self.saved = Some((hash, state.clone())),
+        }
+        self.length += 1;
+        None
+    }
+}
+
+/// `strategy` に従って項を書き換え、正規形まで簡約する
+pub fn normalize(ast: Ast, strategy: Strategy, config: &Config) -> Result<Ast, EvalError> {
+    let initial = ast.size();
+    let mut cycle = Cycle::new();
+    let mut steps = 0;
+    let mut allocations = 0;
+    let mut ast = ast;
+    loop {
+        if config.interruptible() {
+            config.check_interrupt(steps, || Sequence::from(ast.clone()))?;
+        }
+        if config.detect_loops {
+            if let Some(cycle_length) = cycle.observe(&ast) {
+                return Err(EvalError::Diverges { cycle_length });
+            }
+        }
+        let (_, next) = match reduce::step(&ast, strategy) {
+            Some(step) => step,
+            None => return Ok(ast),
+        };
+        if config.out_of_fuel(steps) {
+            return Err(EvalError::OutOfFuel);
+        }
+        // 書き換えるたびに項を作り直すので、新しい項の節点を全て数える
+        let nodes = 2 * next.size() - 1;
+        config.check_nodes(nodes)?;
+        allocations += nodes as u64;
+        config.check_allocations(allocations)?;
+        steps += 1;
+        ast = next;
+        config.check_growth(initial, ast.size())?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn cycle() {
+        // 0, 1, 2, 3, 4, 5, 3, 4, 5, ...
+        let mut cycle = Cycle::new();
+        let found = (0..)
+            .map(|n: u64| if n < 3 { n } else { 3 + (n - 3) % 3 })
+            .enumerate()
+            .find_map(|(n, state)| Some((n, cycle.observe(&state)?)))
+            .unwrap();
+        assert_eq!(found.1, 3);
+        assert!(found.0 < 20);
+    }
+
+    #[test]
+    fn detects_loops() {
+        let config = Config {
+            detect_loops: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            normalize(ast("```sii``sii"), Strategy::Applicative, &config),
+            Err(EvalError::Diverges { cycle_length: 3 })
+        );
+        for strategy in [Strategy::Normal, Strategy::Applicative] {
+            assert_eq!(
+                normalize(ast("```s`kski"), strategy, &config),
+                Ok(ast("`s`ki"))
+            );
+        }
+        // 最左最外では `i が積み重なって同じ項に戻らない
+        let config = Config {
+            detect_loops: true,
+            max_growth: Some(2),
+            ..Config::default()
+        };
+        assert!(matches!(
+            normalize(ast("```sii``sii"), Strategy::Normal, &config),
+            Err(EvalError::Grows { initial: 6, .. })
+        ));
+        // 検出しなければ燃料を使い切る
+        assert_eq!(
+            normalize(
+                ast("```sii``sii"),
+                Strategy::Normal,
+                &Config::with_fuel(100)
+            ),
+            Err(EvalError::OutOfFuel)
+        );
+    }
+
+    #[test]
+    fn growth() {
+        // M = S(SII)I とすると M x = x x x なので、M M は同じ状態に戻らずに大きくなり続ける
+        let growing = ast("```s``siii``s``siii");
+        let config = Config {
+            detect_loops: true,
+            max_growth: Some(4),
+            fuel: Some(10_000),
+            ..Config::default()
+        };
+        match normalize(growing, Strategy::Normal, &config) {
+            Err(EvalError::Grows { initial, size }) => {
+                assert_eq!(initial, 10);
+                assert!(size > 40);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn interrupts() {
+        let token = CancelToken::new();
+        let config = Config {
+            cancel: Some(token.clone()),
+            ..Config::default()
+        };
+        assert_eq!(
+            normalize(ast("``kis"), Strategy::Normal, &config),
+            Ok(ast("i"))
+        );
+        token.cancel();
+        assert_eq!(
+            normalize(ast("``kis"), Strategy::Normal, &config),
+            Err(EvalError::Cancelled {
+                steps: 0,
+                state: Sequence::try_from("``kis").unwrap()
+            })
+        );
+        let config = Config {
+            deadline: Some(Instant::now()),
+            ..Config::default()
+        };
+        assert!(matches!(
+            normalize(ast("```sii``sii"), Strategy::Normal, &config),
+            Err(EvalError::TimedOut { steps: 0, .. })
+        ));
+    }
+}
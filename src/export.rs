@@ -0,0 +1,213 @@
+//! 簡約の記録 (`reduce::Trace`) を1ステップ1行で `Write` に書き出す
+//!
+//! ステップは書いた順に捨てるので、何百万ステップの記録もメモリに溜めずに書き出せる。
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::batch::{json_string, tsv_field, Format};
+use crate::notation::Notation;
+use crate::reduce::Step;
+
+/// 1ステップずつ書き出す。項は `notation`、大きさは原子の数、redex の位置は `reduce::Path` の表示。
+/// 形式は `batch` と同じで、TSV とテキストでは最初に見出しの行を書く
+pub struct Exporter<W> {
+    out: W,
+    format: Format,
+    notation: Notation,
+    started: bool,
+}
+
+impl<W: Write> Exporter<W> {
+    pub fn new(out: W, format: Format, notation: Notation) -> Self {
+        Exporter {
+            out,
+            format,
+            notation,
+            started: false,
+        }
+    }
+
+    /// `step` を1行書き出す
+    pub fn write(&mut self, step: &Step) -> io::Result<()> {
+        let term = self.notation.write(&step.term);
+        let path = step.redex.path.to_string();
+        let size = step.term.size();
+        match self.format {
+            Format::JsonLines => writeln!(
+                self.out,
+                "{{\"step\":{},\"rule\":\"{}\",\"path\":{},\"term\":{},\"size\":{}}}",
+                step.number,
+                step.redex.rule,
+                json_string(&path),
+                json_string(&term),
+                size
+            ),
+            Format::Tsv => {
+                if !self.started {
+                    self.started = true;
+                    writeln!(self.out, "step\trule\tsize\tpath\tterm")?;
+                }
+                writeln!(
+                    self.out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    step.number,
+                    step.redex.rule,
+                    size,
+                    tsv_field(&path),
+                    tsv_field(&term)
+                )
+            }
+            Format::Text => {
+                if !self.started {
+                    self.started = true;
+                    write_text(&mut self.out, "step", "rule", "path", "size", "term")?;
+                }
+                write_text(
+                    &mut self.out,
+                    step.number,
+                    step.redex.rule.to_string(),
+                    path,
+                    size,
+                    term,
+                )
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+// 溜めずに書き出すので、項の前までの列の幅は固定する。幅より長い位置はその行の後ろの列を右へずらす。
+// `Rule` の表示は幅の指定を見ないので、文字列にしてから渡す
+fn write_text(
+    out: &mut impl Write,
+    step: impl fmt::Display,
+    rule: impl fmt::Display,
+    path: impl fmt::Display,
+    size: impl fmt::Display,
+    term: impl fmt::Display,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:>10}  {:<4}  {:<16}  {:>8}  {}",
+        step, rule, path, size, term
+    )
+}
+
+/// `steps` を全て書き出し、書いたステップの数を返す
+pub fn export<W: Write>(
+    steps: impl IntoIterator<Item = Step>,
+    out: W,
+    format: Format,
+    notation: Notation,
+) -> io::Result<u64> {
+    let mut exporter = Exporter::new(out, format, notation);
+    let mut count = 0;
+    for step in steps {
+        exporter.write(&step)?;
+        count += 1;
+    }
+    exporter.into_inner().flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::reduce::{Strategy, Trace};
+    use std::convert::TryFrom;
+
+    fn trace(s: &str) -> Trace {
+        Trace::new(Ast::try_from(s).unwrap(), Strategy::Normal)
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut out = Vec::new();
+        let count = export(
+            trace("``k`ii`is"),
+            &mut out,
+            Format::JsonLines,
+            Notation::Unlambda,
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"step\":1,\"rule\":\"K\",\"path\":\"ε\",\"term\":\"`ii\",\"size\":2}\n\
+             {\"step\":2,\"rule\":\"I\",\"path\":\"ε\",\"term\":\"i\",\"size\":1}\n"
+        );
+    }
+
+    #[test]
+    fn tsv() {
+        let mut out = Vec::new();
+        export(
+            trace("```sii`kk"),
+            &mut out,
+            Format::Tsv,
+            Notation::Applicative,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "step\trule\tsize\tpath\tterm");
+        assert_eq!(lines[1], "1\tS\t6\tε\tI(KK)(I(KK))");
+
+        // redex の位置がどれだけ長くても、列は区切り文字で分かれる
+        let mut deep = String::from("`ii");
+        for _ in 0..20 {
+            deep = format!("`k{}", deep);
+        }
+        let mut out = Vec::new();
+        export(
+            Trace::new(Ast::try_from(deep.as_str()).unwrap(), Strategy::Applicative).take(1),
+            &mut out,
+            Format::Tsv,
+            Notation::Unlambda,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let fields: Vec<&str> = out.lines().nth(1).unwrap().split('\t').collect();
+        assert_eq!(fields.len(), 5);
+        assert!(fields[3].chars().count() > 12, "{}", fields[3]);
+        assert_eq!(fields[4], &deep.replacen("`ii", "i", 1));
+    }
+
+    #[test]
+    fn text() {
+        let mut out = Vec::new();
+        export(
+            trace("```sii`kk"),
+            &mut out,
+            Format::Text,
+            Notation::Applicative,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "      step  rule  path                  size  term\n\
+             \x20        1  S     ε                        6  I(KK)(I(KK))\n\
+             \x20        2  I     f                        5  KK(I(KK))\n\
+             \x20        3  K     ε                        1  K\n"
+        );
+    }
+
+    #[test]
+    fn streams() {
+        // ```sii``sii は止まらないので、書いた分だけで打ち切る
+        let count = export(
+            trace("```sii``sii").take(1000),
+            io::sink(),
+            Format::JsonLines,
+            Notation::Unlambda,
+        )
+        .unwrap();
+        assert_eq!(count, 1000);
+    }
+}
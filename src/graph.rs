@@ -0,0 +1,222 @@
+//! 簡約グラフの幅優先探索と、2つの項の共通の簡約先 (合流) の探索
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::ast::Ast;
+use crate::reduce::{self, Redex, Step};
+
+/// 探索の上限
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct Limits {
+    /// これより大きい (葉の数が多い) 項には進まない
+    pub max_size: usize,
+    /// 始点からこれより多いステップ数の項には進まない
+    pub max_depth: usize,
+    /// 項の数がこれに達したら新しい項を加えない
+    pub max_nodes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_size: 64,
+            max_depth: 16,
+            max_nodes: 10_000,
+        }
+    }
+}
+
+/// 1ステップの簡約を表す辺
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub redex: Redex,
+}
+
+/// 簡約グラフ。項の番号は見つけた順で、始点が0
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Graph {
+    pub nodes: Vec<Ast>,
+    /// 始点からの最短のステップ数
+    pub depths: Vec<usize>,
+    pub edges: Vec<Edge>,
+    /// 上限のために辿らなかった簡約がなければ true
+    pub complete: bool,
+    // 各項に初めて至った辺の番号
+    parents: Vec<Option<usize>>,
+    ids: HashMap<Ast, usize>,
+}
+
+impl Graph {
+    /// 項の番号
+    pub fn id(&self, ast: &Ast) -> Option<usize> {
+        self.ids.get(ast).copied()
+    }
+
+    /// redex を含まない項の番号
+    pub fn normal_forms(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&id| reduce::redexes(&self.nodes[id]).is_empty())
+            .collect()
+    }
+
+    /// 始点から `id` の項に至る最短の簡約
+    pub fn path_to(&self, id: usize) -> Vec<Step> {
+        let mut edges = Vec::new();
+        let mut node = id;
+        while let Some(edge) = self.parents[node] {
+            edges.push(&self.edges[edge]);
+            node = self.edges[edge].from;
+        }
+        edges
+            .into_iter()
+            .rev()
+            .enumerate()
+            .map(|(n, edge)| Step {
+                number: n as u64 + 1,
+                redex: edge.redex.clone(),
+                term: self.nodes[edge.to].clone(),
+            })
+            .collect()
+    }
+}
+
+/// `start` から簡約で到達できる項を幅優先で `limits` の範囲まで辿る。
+///
+/// 始点はその大きさに関わらず含める。辺は簡約元の見つけた順、同じ項の中では redex の前順に並ぶ
+pub fn explore(start: &Ast, limits: Limits) -> Graph {
+    let mut graph = Graph {
+        nodes: vec![start.clone()],
+        depths: vec![0],
+        edges: Vec::new(),
+        complete: true,
+        parents: vec![None],
+        ids: HashMap::new(),
+    };
+    graph.ids.insert(start.clone(), 0);
+    let mut queue = VecDeque::from(vec![0]);
+
+    while let Some(from) = queue.pop_front() {
+        let reducts = reduce::reducts(&graph.nodes[from]);
+        if graph.depths[from] >= limits.max_depth {
+            graph.complete &= reducts.is_empty();
+            continue;
+        }
+        for (redex, reduct) in reducts {
+            if reduct.size() > limits.max_size {
+                graph.complete = false;
+                continue;
+            }
+            let to = match graph.ids.get(&reduct) {
+                Some(&to) => to,
+                None if graph.nodes.len() >= limits.max_nodes => {
+                    graph.complete = false;
+                    continue;
+                }
+                None => {
+                    let to = graph.nodes.len();
+                    graph.ids.insert(reduct.clone(), to);
+                    graph.nodes.push(reduct);
+                    graph.depths.push(graph.depths[from] + 1);
+                    graph.parents.push(Some(graph.edges.len()));
+                    queue.push_back(to);
+                    to
+                }
+            };
+            graph.edges.push(Edge { from, to, redex });
+        }
+    }
+    graph
+}
+
+/// `join` で見つかった共通の簡約先
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Join {
+    pub reduct: Ast,
+    /// 1つ目の項から `reduct` への簡約
+    pub left: Vec<Step>,
+    /// 2つ目の項から `reduct` への簡約
+    pub right: Vec<Step>,
+}
+
+/// `left` と `right` の両方から簡約で到達できる項のうち、ステップ数の合計が最小のものを探す。
+/// `limits` の範囲に見つからなければ `None`
+pub fn join(left: &Ast, right: &Ast, limits: Limits) -> Option<Join> {
+    let from_left = explore(left, limits);
+    let from_right = explore(right, limits);
+    let (l, r) = (0..from_left.nodes.len())
+        .filter_map(|l| Some((l, from_right.id(&from_left.nodes[l])?)))
+        .min_by_key(|&(l, r)| from_left.depths[l] + from_right.depths[r])?;
+    Some(Join {
+        reduct: from_left.nodes[l].clone(),
+        left: from_left.path_to(l),
+        right: from_right.path_to(r),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reduce::{Strategy, Trace};
+    use std::convert::TryFrom;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn explores() {
+        let graph = explore(&ast("``k`ii`is"), Limits::default());
+        assert!(graph.complete);
+        assert_eq!((graph.nodes.len(), graph.edges.len()), (6, 9));
+        assert_eq!(graph.normal_forms(), vec![graph.id(&ast("i")).unwrap()]);
+        let path = graph.path_to(graph.id(&ast("i")).unwrap());
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[1].term, ast("i"));
+
+        // ```sii``sii は止まらないので、上限で打ち切る
+        let limits = Limits {
+            max_depth: 3,
+            ..Limits::default()
+        };
+        let graph = explore(&ast("```sii``sii"), limits);
+        assert!(!graph.complete);
+        assert!(graph.depths.iter().all(|&depth| depth <= 3));
+        let graph = explore(
+            &ast("```sii``sii"),
+            Limits {
+                max_nodes: 2,
+                ..Limits::default()
+            },
+        );
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(!graph.complete);
+    }
+
+    #[test]
+    fn joins() {
+        // 同じ項の2つの簡約先は合流する (Church–Rosser)
+        let term = ast("``k`ii```sk`ii`ks");
+        let reducts = reduce::reducts(&term);
+        for (_, left) in &reducts {
+            for (_, right) in &reducts {
+                let joined = join(left, right, Limits::default()).unwrap();
+                let last = |steps: &[Step], start: &Ast| {
+                    steps.last().map_or(start.clone(), |step| step.term.clone())
+                };
+                assert_eq!(last(&joined.left, left), joined.reduct);
+                assert_eq!(last(&joined.right, right), joined.reduct);
+            }
+        }
+        // 戦略の違う簡約の途中の項も合流する
+        let term = ast("``k`ii```sii``sii");
+        let normal = Trace::new(term.clone(), Strategy::Normal).next().unwrap();
+        let applicative = Trace::new(term, Strategy::Applicative).nth(2).unwrap();
+        let joined = join(&normal.term, &applicative.term, Limits::default()).unwrap();
+        assert_eq!(joined.reduct, ast("i"));
+        assert_eq!((joined.left.len(), joined.right.len()), (1, 1));
+
+        assert_eq!(join(&ast("s"), &ast("k"), Limits::default()), None);
+    }
+}
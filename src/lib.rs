@@ -1,10 +1,32 @@
 pub mod ast;
+pub mod batch;
+pub mod compile;
+pub mod debugger;
+pub mod diagnostic;
+pub mod differential;
+pub mod dot;
+pub mod enumerate;
 pub mod error;
+pub mod eval;
+pub mod export;
+pub mod graph;
 pub mod lambda;
+pub mod notation;
+pub mod optimize;
+pub mod prove;
+pub mod random;
+pub mod reduce;
 pub mod sequence;
+#[cfg(feature = "serde")]
+mod serialize;
 pub mod stack;
+pub mod stats;
+pub mod stream;
+pub mod superopt;
+pub mod symbolic;
 pub mod term;
 pub mod token;
+pub mod types;
 
 /*
     s, k, iはwell-formed/validである
@@ -1,13 +1,617 @@
 use std::convert::TryFrom;
 use std::env::args;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::process::exit;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use ski_rs::ast::Ast;
+use ski_rs::batch::{self, Format};
+use ski_rs::compile::compile;
+use ski_rs::diagnostic::{validate, Diagnostic, Locate};
+use ski_rs::error::{
+    EvalError, FormulaError, InvalidError, LexiconError, NotationError, StructureError,
+};
+use ski_rs::eval::Config;
+use ski_rs::notation::{Applicative, Notation};
 use ski_rs::sequence::Sequence;
 use ski_rs::stack::Stax;
+use ski_rs::stats::Stats;
+
+const USAGE: &str = "\
+usage: ski-rs <command> [options] [term]
+
+commands:
+  eval      evaluate term to normal form
+  parse     check that term is well-formed
+  fmt       convert term to another notation
+  compile   compile a lambda term (\\x y. x) to SKI
+  stats     show the size of term and of its evaluation
+  batch     evaluate each line (unlambda) and write one record per line
+
+options:
+  -f, --file <path>   read term from path (- for stdin)
+  --from <notation>   notation of the input: unlambda, applicative
+  --to <notation>     notation of the output: unlambda, applicative
+  --fuel <n>          give up after n rule applications
+                      (batch: per line, default 1000000)
+  --detect-loops      stop when the evaluation returns to an earlier state
+  --max-growth <n>    stop when the term grows to n times its initial size
+  --max-nodes <n>     stop before an intermediate term has more than n nodes
+  --max-stack <n>     stop when the evaluation stack grows deeper than n
+  --max-allocations <n>
+                      stop before more than n nodes in total are allocated
+  --timeout <ms>      give up after ms milliseconds
+  --stats             eval: also print rule counts, sizes, allocations and time
+                      (on stderr when a limit is hit)
+  --format <format>   batch output: tsv, json, text (default: tsv)
+  --jobs <n>          batch threads (default: number of CPUs)
+
+eval and stats are unbounded by default: without --fuel, --timeout or another
+limit, a term with no normal form runs until interrupted.
+Without term and --file, term is read from stdin.
+The notation of the input is guessed from its first character unless --from is given.";
+
+/// 終了コード
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Exit {
+    Usage = 2,
+    Lexical = 3,
+    Formula = 4,
+    Limit = 5,
+    Io = 6,
+}
+
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Lexicon(LexiconError),
+    Notation(NotationError),
+    /// 入力の構造のエラー
+    Structure(StructureError),
+    /// 評価の途中の構造のエラー。位置を持たない
+    Formula(FormulaError),
+    OutOfFuel(u64),
+    /// 燃料以外の評価の制限
+    Limit(EvalError),
+    Io(String, io::Error),
+    /// `eval --stats` で評価が失敗したときの、エラーとそこまでの統計
+    WithStats(Box<CliError>, Stats),
+}
+
+impl CliError {
+    fn exit(&self) -> Exit {
+        match self {
+            Self::Usage(_) => Exit::Usage,
+            Self::Lexicon(_) => Exit::Lexical,
+            Self::Notation(NotationError::UnexpectedChar(_, _))
+            | Self::Notation(NotationError::UnknownName(_, _)) => Exit::Lexical,
+            Self::Notation(_) | Self::Structure(_) | Self::Formula(_) => Exit::Formula,
+            Self::OutOfFuel(_) | Self::Limit(_) => Exit::Limit,
+            Self::Io(_, _) => Exit::Io,
+            Self::WithStats(e, _) => e.exit(),
+        }
+    }
+}
+
+impl CliError {
+    // 入力の中に位置を持つエラーならば、その位置。Unlambda 記法では全ての誤りを集める
+    fn diagnostics(&self, source: &str) -> Vec<Diagnostic> {
+        match self {
+            Self::Lexicon(_) | Self::Structure(_) => validate(source),
+            Self::Notation(e) => vec![e.locate(source)],
+            Self::WithStats(e, _) => e.diagnostics(source),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{}", message),
+            Self::Lexicon(err) => write!(f, "lexical error: {}", err),
+            Self::Notation(err) => write!(f, "syntax error: {}", err),
+            Self::Structure(err) => write!(f, "formula error: {}", err),
+            Self::Formula(err) => write!(f, "formula error: {}", err),
+            Self::OutOfFuel(fuel) => write!(f, "no normal form within {} steps", fuel),
+            Self::Limit(err) => write!(f, "no normal form: {}", err),
+            Self::Io(path, err) => write!(f, "{}: {}", path, err),
+            Self::WithStats(err, stats) => write!(f, "{}\n{}", err, stats),
+        }
+    }
+}
+
+impl From<LexiconError> for CliError {
+    fn from(e: LexiconError) -> Self {
+        CliError::Lexicon(e)
+    }
+}
+
+impl From<InvalidError> for CliError {
+    fn from(e: InvalidError) -> Self {
+        match e {
+            InvalidError::LexiconError(e) => CliError::Lexicon(e),
+            InvalidError::FormulaError(e) => CliError::Structure(e),
+        }
+    }
+}
+
+impl From<NotationError> for CliError {
+    fn from(e: NotationError) -> Self {
+        CliError::Notation(e)
+    }
+}
+
+impl From<FormulaError> for CliError {
+    fn from(e: FormulaError) -> Self {
+        CliError::Formula(e)
+    }
+}
+
+// --fuel を与えないときの batch の1行あたりの燃料。止まらない行が全体を止めないようにする
+const BATCH_FUEL: u64 = 1_000_000;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Command {
+    Eval,
+    Parse,
+    Fmt,
+    Compile,
+    Stats,
+    Batch,
+}
+
+fn notation(name: &str) -> Result<Notation, CliError> {
+    Notation::from_name(name).ok_or_else(|| CliError::Usage(format!("unknown notation {}", name)))
+}
+
+fn read(notation: Notation, source: &str) -> Result<Ast, CliError> {
+    match notation {
+        Notation::Unlambda => Ok(Ast::parse_skipping_whitespace(source)?),
+        Notation::Applicative => Ok(Ast::from(Applicative::try_from(source)?)),
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Input {
+    Term(String),
+    File(String),
+    Stdin,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Options {
+    command: Command,
+    input: Input,
+    from: Option<Notation>,
+    to: Option<Notation>,
+    config: Config,
+    format: Format,
+    jobs: Option<usize>,
+    stats: bool,
+    /// 入力を読み終えてから数える。評価の直前に `config.deadline` にする
+    timeout: Option<Duration>,
+}
+
+impl Options {
+    // 今から `timeout` 後を期限にした評価の設定
+    fn config(&self) -> Config {
+        Config {
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+            ..self.config.clone()
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, CliError> {
+    let mut args = args.iter();
+    let command = match args.next().map(String::as_str) {
+        Some("eval") => Command::Eval,
+        Some("parse") => Command::Parse,
+        Some("fmt") => Command::Fmt,
+        Some("compile") => Command::Compile,
+        Some("stats") => Command::Stats,
+        Some("batch") => Command::Batch,
+        Some(other) => return Err(CliError::Usage(format!("unknown command {}", other))),
+        None => return Err(CliError::Usage("missing command".to_string())),
+    };
+    let mut options = Options {
+        command,
+        input: Input::Stdin,
+        from: None,
+        to: None,
+        config: Config::default(),
+        format: Format::Tsv,
+        jobs: None,
+        stats: false,
+        timeout: None,
+    };
+    let mut term = None;
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| CliError::Usage(format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "-f" | "--file" => file = Some(value()?.clone()),
+            "--from" => options.from = Some(notation(value()?)?),
+            "--to" => options.to = Some(notation(value()?)?),
+            "--fuel" => options.config.fuel = Some(number(arg, value()?)?),
+            "--detect-loops" => options.config.detect_loops = true,
+            "--stats" => options.stats = true,
+            "--max-growth" => options.config.max_growth = Some(number(arg, value()?)?),
+            "--max-nodes" => options.config.max_nodes = Some(number(arg, value()?)?),
+            "--max-stack" => options.config.max_stack = Some(number(arg, value()?)?),
+            "--max-allocations" => options.config.max_allocations = Some(number(arg, value()?)?),
+            "--timeout" => options.timeout = Some(Duration::from_millis(number(arg, value()?)?)),
+            "--jobs" => options.jobs = Some(number(arg, value()?)?),
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "tsv" => Format::Tsv,
+                    "json" => Format::JsonLines,
+                    "text" => Format::Text,
+                    other => return Err(CliError::Usage(format!("unknown format {}", other))),
+                }
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(CliError::Usage(format!("unknown option {}", arg)))
+            }
+            _ if term.is_none() => term = Some(arg.clone()),
+            _ => return Err(CliError::Usage(format!("unexpected argument {}", arg))),
+        }
+    }
+    options.input = match (term, file) {
+        (Some(_), Some(_)) => {
+            return Err(CliError::Usage(
+                "give either a term or --file, not both".to_string(),
+            ))
+        }
+        (Some(term), None) => Input::Term(term),
+        (None, Some(file)) if file != "-" => Input::File(file),
+        (None, _) => Input::Stdin,
+    };
+    Ok(options)
+}
+
+fn number<T: FromStr>(option: &str, value: &str) -> Result<T, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError::Usage(format!("invalid value {} for {}", value, option)))
+}
+
+fn read_input(input: &Input) -> Result<String, CliError> {
+    match input {
+        Input::Term(term) => Ok(term.clone()),
+        Input::File(path) => fs::read_to_string(path).map_err(|e| CliError::Io(path.clone(), e)),
+        Input::Stdin => {
+            let mut source = String::new();
+            io::stdin()
+                .read_to_string(&mut source)
+                .map_err(|e| CliError::Io("<stdin>".to_string(), e))?;
+            Ok(source)
+        }
+    }
+}
+
+// 正規形と、評価に使った Stax
+fn evaluate(ast: Ast, config: &Config) -> Result<(Ast, Stax), CliError> {
+    let mut stax = Stax::from(Sequence::from(ast));
+    let seq = stax.eval_with(config).map_err(|e| eval_error(e, config))?;
+    Ok((Ast::try_from(seq)?, stax))
+}
+
+fn eval_error(e: EvalError, config: &Config) -> CliError {
+    match e {
+        EvalError::FormulaError(e) => e.into(),
+        EvalError::OutOfFuel => CliError::OutOfFuel(config.fuel.unwrap_or(0)),
+        e => CliError::Limit(e),
+    }
+}
+
+// 字句と構造のエラーの位置は source の先頭から数えるので、source は切り詰めずに読む
+fn run(options: &Options, source: &str) -> Result<String, CliError> {
+    if options.command == Command::Batch {
+        return Ok(run_batch(options, source));
+    }
+    if options.command == Command::Compile {
+        let ast = compile(source)?;
+        return Ok(options.to.unwrap_or(Notation::Unlambda).write(&ast));
+    }
+
+    let from = options
+        .from
+        .unwrap_or_else(|| Notation::guess(source.trim()));
+    let ast = read(from, source)?;
+    match options.command {
+        Command::Parse => Ok(String::new()),
+        Command::Fmt => Ok(options.to.unwrap_or_else(|| from.other()).write(&ast)),
+        Command::Eval if options.stats => {
+            let config = options.config();
+            let mut stax = Stax::from(Sequence::from(ast));
+            let (result, stats) = stax.eval_with_stats(&config);
+            let normal = result
+                .map_err(|e| eval_error(e, &config))
+                .and_then(|seq| Ok(Ast::try_from(seq)?));
+            match normal {
+                Ok(normal) => Ok(format!(
+                    "{}\n{}",
+                    options.to.unwrap_or(from).write(&normal),
+                    stats
+                )),
+                Err(e) => Err(CliError::WithStats(Box::new(e), stats)),
+            }
+        }
+        Command::Eval => {
+            let (normal, _) = evaluate(ast, &options.config())?;
+            Ok(options.to.unwrap_or(from).write(&normal))
+        }
+        Command::Stats => {
+            let (atoms, depth) = (ast.size(), ast.depth());
+            let (normal, stax) = evaluate(ast, &options.config())?;
+            Ok(format!(
+                "atoms\t{}\ntokens\t{}\ndepth\t{}\nsteps\t{}\nmax stack depth\t{}\nnormal form atoms\t{}",
+                atoms,
+                2 * atoms - 1,
+                depth,
+                stax.steps(),
+                stax.max_depth(),
+                normal.size()
+            ))
+        }
+        Command::Compile | Command::Batch => unreachable!(),
+    }
+}
+
+// 失敗した行もレコードとして書き出し、全体は失敗させない
+fn run_batch(options: &Options, source: &str) -> String {
+    let lines: Vec<String> = source.lines().map(String::from).collect();
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let config = options.config();
+    let config = Config {
+        fuel: config.fuel.or(Some(BATCH_FUEL)),
+        ..config
+    };
+    let records = batch::evaluate_all(&lines, &config, jobs);
+    options
+        .format
+        .header()
+        .map(String::from)
+        .into_iter()
+        .chain(records.iter().map(|record| options.format.format(record)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
 fn main() {
-    let arg = args().nth(1).unwrap();
-    let arg = arg.as_str();
-    let mut stax = Stax::from(Sequence::try_from(arg).unwrap());
-    let result = stax.eval().unwrap();
-    println!("{}", Into::<String>::into(result));
+    let args: Vec<String> = args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let mut source = String::new();
+    let result = parse_args(&args).and_then(|options| {
+        source = read_input(&options.input)?;
+        run(&options, &source)
+    });
+    match result {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+        }
+        Err(e) => {
+            let diagnostics = e.diagnostics(&source);
+            if diagnostics.is_empty() {
+                eprintln!("ski-rs: {}", e);
+            }
+            for diagnostic in diagnostics {
+                eprintln!("{}\n", diagnostic.render(&source));
+            }
+            if let CliError::Usage(_) = e {
+                eprintln!("{}", USAGE);
+            }
+            exit(e.exit() as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Options, CliError> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        parse_args(&args)
+    }
+
+    fn run_args(args: &[&str]) -> Result<String, CliError> {
+        let options = options(args)?;
+        run(&options, &read_input(&options.input)?)
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(
+            options(&["eval", "--fuel", "10", "SKK"]).unwrap(),
+            Options {
+                command: Command::Eval,
+                input: Input::Term("SKK".to_string()),
+                from: None,
+                to: None,
+                config: Config::with_fuel(10),
+                format: Format::Tsv,
+                jobs: None,
+                stats: false,
+                timeout: None,
+            }
+        );
+        assert_eq!(options(&["parse"]).unwrap().input, Input::Stdin);
+        // 期限は評価の直前に決めるので、引数を読んだ時点では設定に入らない
+        let timeout = options(&["eval", "--timeout", "1500"]).unwrap();
+        assert_eq!(timeout.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(timeout.config.deadline, None);
+        let before = Instant::now();
+        let deadline = timeout.config().deadline.unwrap();
+        assert!(deadline >= before + Duration::from_millis(1500));
+        assert_eq!(options(&["parse", "-f", "-"]).unwrap().input, Input::Stdin);
+        assert_eq!(
+            options(&["fmt", "--file", "a.ski", "--to", "unlambda"]).unwrap(),
+            Options {
+                command: Command::Fmt,
+                input: Input::File("a.ski".to_string()),
+                from: None,
+                to: Some(Notation::Unlambda),
+                config: Config::default(),
+                format: Format::Tsv,
+                jobs: None,
+                stats: false,
+                timeout: None,
+            }
+        );
+        for args in &[
+            &[][..],
+            &["run"],
+            &["eval", "--fuel"],
+            &["eval", "--fuel", "x"],
+            &["eval", "--to", "lisp"],
+            &["batch", "--format", "xml"],
+            &["batch", "--jobs", "-1"],
+            &["eval", "--verbose"],
+            &["eval", "SKK", "S"],
+            &["eval", "-f", "a.ski", "S"],
+        ] {
+            assert_eq!(options(args).unwrap_err().exit(), Exit::Usage, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(run_args(&["eval", "```sski"]).unwrap(), "``si`ki");
+        assert_eq!(run_args(&["eval", "SKKS"]).unwrap(), "S");
+        assert_eq!(
+            run_args(&["eval", "--to", "applicative", "```sski"]).unwrap(),
+            "SI(KI)"
+        );
+        assert_eq!(run_args(&["parse", "S(KS)K"]).unwrap(), "");
+        assert_eq!(run_args(&["fmt", "S(KS)K"]).unwrap(), "``s`ksk");
+        assert_eq!(run_args(&["fmt", "``s`ksk"]).unwrap(), "S(KS)K");
+        assert_eq!(
+            run_args(&["fmt", "--from", "applicative", "S"]).unwrap(),
+            "s"
+        );
+        assert_eq!(
+            run_args(&["compile", r"\f g x. f (g x)"]).unwrap(),
+            "``s`ksk"
+        );
+        assert_eq!(
+            run_args(&["stats", "```sski"]).unwrap(),
+            "atoms\t4\ntokens\t7\ndepth\t3\nsteps\t1\nmax stack depth\t4\nnormal form atoms\t4"
+        );
+        let output = run_args(&["eval", "--stats", "SSKI"]).unwrap();
+        assert!(output.starts_with(
+            "SI(KI)\nsteps\t1\ns steps\t1\nk steps\t0\ni steps\t0\n\
+             max stack depth\t4\npeak atoms\t4\nfinal atoms\t4\n"
+        ));
+        assert!(output.contains("\nelapsed\t"));
+
+        // 制限に達しても、そこまでの統計をエラーと一緒に出す
+        let e = run_args(&["eval", "--stats", "--fuel", "10", "SII(SII)"]).unwrap_err();
+        assert_eq!(e.exit(), Exit::Limit);
+        assert!(e
+            .to_string()
+            .starts_with("no normal form within 10 steps\nsteps\t10\ns steps\t"));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let exit = |args: &[&str]| run_args(args).unwrap_err().exit();
+        assert_eq!(exit(&["eval", "``sx"]), Exit::Lexical);
+        assert_eq!(exit(&["eval", "SxK"]), Exit::Lexical);
+        assert_eq!(exit(&["compile", r"\x. y"]), Exit::Lexical);
+        assert_eq!(exit(&["eval", "``sk"]), Exit::Formula);
+        assert_eq!(exit(&["parse", "S(K"]), Exit::Formula);
+        assert_eq!(exit(&["eval", "--fuel", "100", "SII(SII)"]), Exit::Limit);
+        assert_eq!(exit(&["eval", "--detect-loops", "SII(SII)"]), Exit::Limit);
+        assert_eq!(
+            exit(&["eval", "--max-growth", "3", "S(SII)I(S(SII)I)"]),
+            Exit::Limit
+        );
+        assert_eq!(exit(&["eval", "--timeout", "0", "SII(SII)"]), Exit::Limit);
+        for limit in &["--max-nodes", "--max-stack", "--max-allocations"] {
+            assert_eq!(
+                exit(&["eval", limit, "30", "S(SII)I(S(SII)I)"]),
+                Exit::Limit
+            );
+        }
+        assert_eq!(exit(&["eval", "-f", "/nonexistent/file.ski"]), Exit::Io);
+    }
+
+    #[test]
+    fn batch() {
+        let path = std::env::temp_dir().join(format!("ski-rs-batch-{}.txt", std::process::id()));
+        fs::write(&path, "```sski\n`x\n\n```sii``sii\n").unwrap();
+        let result = run_args(&[
+            "batch",
+            "--fuel",
+            "10",
+            "--jobs",
+            "2",
+            "--format",
+            "json",
+            "-f",
+            path.to_str().unwrap(),
+        ]);
+        fs::remove_file(&path).unwrap();
+        let output = result.unwrap();
+        let errors: Vec<&str> = output
+            .lines()
+            .map(|line| &line[line.rfind("\"error\":").unwrap()..])
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "\"error\":null}",
+                "\"error\":\"lexical\"}",
+                "\"error\":\"out_of_fuel\"}"
+            ]
+        );
+        assert!(output.starts_with("{\"line\":1,"));
+
+        // --fuel がなくても、止まらない行は既定の燃料で打ち切る
+        let output = run_args(&["batch", "```sii``sii"]).unwrap();
+        let record = output.lines().nth(1).unwrap();
+        assert!(record.ends_with("\tout_of_fuel"), "{}", record);
+        assert!(
+            record.contains(&format!("\t{}\t", BATCH_FUEL)),
+            "{}",
+            record
+        );
+    }
+
+    #[test]
+    fn diagnostics() {
+        let source = "``s\n  `k";
+        let e = run_args(&["eval", source]).unwrap_err();
+        assert_eq!(
+            e.diagnostics(source).last().unwrap().to_string(),
+            "2:3: expected 2 more atoms after this application"
+        );
+        let source = " S(K";
+        let e = run_args(&["parse", source]).unwrap_err();
+        assert_eq!(e.diagnostics(source)[0].span.to_string(), "1:3");
+        assert_eq!(CliError::OutOfFuel(1).diagnostics(""), vec![]);
+
+        // 字句の誤りがあっても構造の誤りまで全て報告する
+        let source = "`?s\n`k";
+        let e = run_args(&["parse", source]).unwrap_err();
+        assert_eq!(e.exit(), Exit::Lexical);
+        assert_eq!(e.diagnostics(source).len(), 2);
+    }
 }
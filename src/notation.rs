@@ -0,0 +1,338 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::ast::{Ast, AstInner};
+use crate::error::{FormulaError, NotationError};
+use crate::sequence::Sequence;
+use crate::token::Atom;
+
+/// 左結合の関数適用記法 (`S(KS)K`, `SII(SII)`) で表したSKI formula
+///
+/// 原子コンビネータは大文字の `S`, `K`, `I` で書く。空白は任意の位置に置いてよい。
+/// 表示する際は必要最小限の括弧だけを出力する。
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Applicative(Ast);
+
+impl From<Ast> for Applicative {
+    fn from(ast: Ast) -> Self {
+        Applicative(ast)
+    }
+}
+
+impl From<Applicative> for Ast {
+    fn from(app: Applicative) -> Self {
+        app.0
+    }
+}
+
+impl TryFrom<Sequence> for Applicative {
+    type Error = FormulaError;
+
+    fn try_from(seq: Sequence) -> Result<Self, Self::Error> {
+        Ok(Applicative(Ast::try_from(seq)?))
+    }
+}
+
+impl From<Applicative> for Sequence {
+    fn from(app: Applicative) -> Self {
+        Sequence::from(app.0)
+    }
+}
+
+impl TryFrom<&str> for Applicative {
+    type Error = NotationError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Ok(Applicative(parse_with(s, &atom, &apply, None)?))
+    }
+}
+
+impl TryFrom<String> for Applicative {
+    type Error = NotationError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Applicative::try_from(s.as_str())
+    }
+}
+
+impl fmt::Display for Applicative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_term(f, &self.0)
+    }
+}
+
+impl Applicative {
+    pub fn as_ast(&self) -> &Ast {
+        &self.0
+    }
+
+    /// 小文字で始まる名前 (`[a-z_][a-z0-9_]*`) を `names` で項に置き換えながら読む
+    pub fn parse_with_names(
+        s: &str,
+        names: &dyn Fn(&str) -> Option<Ast>,
+    ) -> Result<Self, NotationError> {
+        Ok(Applicative(parse_with(s, &atom, &apply, Some(names))?))
+    }
+}
+
+fn atom(c: char) -> Option<Ast> {
+    match c {
+        'S' => Some(Ast::Leaf(Atom::S)),
+        'K' => Some(Ast::Leaf(Atom::K)),
+        'I' => Some(Ast::Leaf(Atom::I)),
+        _ => None,
+    }
+}
+
+fn apply(function: Ast, argument: Ast) -> Ast {
+    Ast::Apply(AstInner::new(function, argument))
+}
+
+/// 項の書き方
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Notation {
+    /// `` ``s`ksk ``
+    Unlambda,
+    /// `S(KS)K`
+    Applicative,
+}
+
+impl Notation {
+    /// `"unlambda"` か `"applicative"`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unlambda" => Some(Notation::Unlambda),
+            "applicative" => Some(Notation::Applicative),
+            _ => None,
+        }
+    }
+
+    /// `` ` `` で始まるか小文字の原子コンビネータ1つならば Unlambda 記法
+    pub fn guess(source: &str) -> Self {
+        match source {
+            "s" | "k" | "i" => Notation::Unlambda,
+            _ if source.starts_with('`') => Notation::Unlambda,
+            _ => Notation::Applicative,
+        }
+    }
+
+    /// もう一方の記法
+    pub fn other(self) -> Self {
+        match self {
+            Notation::Unlambda => Notation::Applicative,
+            Notation::Applicative => Notation::Unlambda,
+        }
+    }
+
+    pub fn write(self, ast: &Ast) -> String {
+        match self {
+            Notation::Unlambda => String::from(Sequence::from(ast.clone())),
+            Notation::Applicative => Applicative::from(ast.clone()).to_string(),
+        }
+    }
+}
+
+// 関数側は左結合なので括弧不要、引数側は適用であるときに限り括弧が必要
+fn write_term(f: &mut fmt::Formatter<'_>, ast: &Ast) -> fmt::Result {
+    match ast {
+        Ast::Leaf(atom) => write_atom(f, *atom),
+        Ast::Apply(inner) => {
+            write_term(f, inner.function())?;
+            match inner.argument() {
+                Ast::Leaf(atom) => write_atom(f, *atom),
+                argument => {
+                    write!(f, "(")?;
+                    write_term(f, argument)?;
+                    write!(f, ")")
+                }
+            }
+        }
+    }
+}
+
+fn write_atom(f: &mut fmt::Formatter<'_>, atom: Atom) -> fmt::Result {
+    match atom {
+        Atom::S => write!(f, "S"),
+        Atom::K => write!(f, "K"),
+        Atom::I => write!(f, "I"),
+    }
+}
+
+// 名前から葉への解釈
+pub(crate) type Names<'a, T> = &'a dyn Fn(&str) -> Option<T>;
+
+/// 葉となる1文字の解釈と適用の組み立て方を与えて、関数適用記法を読む。
+/// `names` を与えると、小文字で始まる名前もその解釈で葉として読む
+pub(crate) fn parse_with<T>(
+    s: &str,
+    leaf: &dyn Fn(char) -> Option<T>,
+    apply: &dyn Fn(T, T) -> T,
+    names: Option<Names<T>>,
+) -> Result<T, NotationError> {
+    let mut parser = Parser {
+        chars: s.chars().collect(),
+        point: 0,
+        leaf,
+        apply,
+        names,
+    };
+    let term = parser.term()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(term),
+        Some(')') => Err(NotationError::UnmatchedParen(parser.point as u32)),
+        Some(c) => Err(NotationError::UnexpectedChar(parser.point as u32, c)),
+    }
+}
+
+struct Parser<'a, T> {
+    chars: Vec<char>,
+    point: usize,
+    leaf: &'a dyn Fn(char) -> Option<T>,
+    apply: &'a dyn Fn(T, T) -> T,
+    names: Option<Names<'a, T>>,
+}
+
+impl<T> Parser<'_, T> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.point).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.point += 1;
+        }
+    }
+
+    // term := primary primary*
+    fn term(&mut self) -> Result<T, NotationError> {
+        let mut function = match self.primary()? {
+            Some(term) => term,
+            None => return Err(NotationError::EmptyTerm(self.point as u32)),
+        };
+        while let Some(argument) = self.primary()? {
+            function = (self.apply)(function, argument);
+        }
+        Ok(function)
+    }
+
+    // primary := leaf | "(" term ")"
+    fn primary(&mut self) -> Result<Option<T>, NotationError> {
+        self.skip_whitespace();
+        let term = match self.peek() {
+            Some('(') => {
+                let open = self.point;
+                self.point += 1;
+                let term = self.term()?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(NotationError::UnclosedParen(open as u32));
+                }
+                term
+            }
+            Some(')') | None => return Ok(None),
+            Some(c) if self.names.is_some() && (c.is_ascii_lowercase() || c == '_') => {
+                return self.name().map(Some)
+            }
+            Some(c) => match (self.leaf)(c) {
+                Some(term) => term,
+                None => return Err(NotationError::UnexpectedChar(self.point as u32, c)),
+            },
+        };
+        self.point += 1;
+        Ok(Some(term))
+    }
+
+    // name := [a-z_][a-z0-9_]*
+    fn name(&mut self) -> Result<T, NotationError> {
+        let start = self.point;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+                break;
+            }
+            self.point += 1;
+        }
+        let name: String = self.chars[start..self.point].iter().collect();
+        match (self.names.unwrap())(&name) {
+            Some(term) => Ok(term),
+            None => Err(NotationError::UnknownName(start as u32, name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(applicative: &str, unlambda: &str) {
+        let app = Applicative::try_from(applicative).unwrap();
+        let seq = Sequence::try_from(unlambda).unwrap();
+        assert_eq!(Sequence::from(app.clone()), seq);
+        assert_eq!(Applicative::try_from(seq), Ok(app.clone()));
+        assert_eq!(app.to_string(), applicative);
+    }
+
+    #[test]
+    fn parse_and_print() {
+        roundtrip("S", "s");
+        roundtrip("SKI", "``ski");
+        roundtrip("S(KS)K", "``s`ksk");
+        roundtrip("SII(SII)", "```sii``sii");
+        roundtrip("S(K(SI))(S(KK)I)", "``s`k`si``s`kki");
+    }
+
+    #[test]
+    fn minimal_parens() {
+        let app = Applicative::try_from("((S)(K) ((K) I))").unwrap();
+        assert_eq!(app.to_string(), "SK(KI)");
+        let app = Applicative::try_from(" S ( K S ) K ").unwrap();
+        assert_eq!(app.to_string(), "S(KS)K");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Applicative::try_from("SKx"),
+            Err(NotationError::UnexpectedChar(2, 'x'))
+        );
+        assert_eq!(
+            Applicative::try_from("S(KS"),
+            Err(NotationError::UnclosedParen(1))
+        );
+        assert_eq!(
+            Applicative::try_from("SK)I"),
+            Err(NotationError::UnmatchedParen(2))
+        );
+        assert_eq!(
+            Applicative::try_from("S()"),
+            Err(NotationError::EmptyTerm(2))
+        );
+        assert_eq!(
+            Applicative::try_from("  "),
+            Err(NotationError::EmptyTerm(2))
+        );
+    }
+
+    #[test]
+    fn names() {
+        let names = |name: &str| match name {
+            "b" => Some(Ast::try_from("``s`ksk").unwrap()),
+            "omega_2" => Some(Ast::try_from("``sii").unwrap()),
+            _ => None,
+        };
+        let app = Applicative::parse_with_names("b K (omega_2 I)", &names).unwrap();
+        assert_eq!(app.to_string(), "S(KS)KK(SIII)");
+        assert_eq!(
+            Applicative::parse_with_names("S x", &names),
+            Err(NotationError::UnknownName(2, "x".to_string()))
+        );
+        // 名前を使わなければ小文字は読めない
+        assert_eq!(
+            Applicative::try_from("Sb"),
+            Err(NotationError::UnexpectedChar(1, 'b'))
+        );
+    }
+}
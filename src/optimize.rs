@@ -0,0 +1,193 @@
+//! 外延的に等しく、より小さい項への書き換え
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::ast::{Ast, AstInner};
+use crate::symbolic::Term;
+
+/// 書き換えに使う恒等式の一覧。関数適用記法で、小文字は任意の部分項を表す。
+///
+/// どれも左辺と右辺が外延的に等しく、右辺の方が葉の数が少ない。
+pub const IDENTITIES: &[(&str, &str)] = &[
+    ("Ix", "x"),
+    ("Kxy", "x"),
+    ("S(KI)", "I"),
+    ("SKx", "I"),
+    ("S(Kx)I", "x"),
+    ("S(Kx)(Ky)", "K(xy)"),
+];
+
+/// `optimize` の結果。大きさは葉の数
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Optimized {
+    pub ast: Ast,
+    pub before: usize,
+    pub after: usize,
+}
+
+/// `IDENTITIES` の書き換えを、どれも適用できなくなるまで繰り返す
+pub fn optimize(ast: Ast) -> Optimized {
+    let identities: Vec<(Term, Term)> = IDENTITIES
+        .iter()
+        .map(|(lhs, rhs)| (Term::try_from(*lhs).unwrap(), Term::try_from(*rhs).unwrap()))
+        .collect();
+    let before = ast.size();
+    let mut ast = ast;
+    loop {
+        let mut changed = false;
+        ast = pass(ast, &identities, &mut changed);
+        if !changed {
+            break;
+        }
+    }
+    let after = ast.size();
+    Optimized { ast, before, after }
+}
+
+// 葉から根に向かって1回書き換える
+fn pass(ast: Ast, identities: &[(Term, Term)], changed: &mut bool) -> Ast {
+    enum Frame {
+        Visit(Ast),
+        Build,
+    }
+
+    let mut rest = vec![Frame::Visit(ast)];
+    let mut done = Vec::new();
+    while let Some(frame) = rest.pop() {
+        match frame {
+            Frame::Visit(Ast::Leaf(atom)) => done.push(Ast::Leaf(atom)),
+            Frame::Visit(Ast::Apply(inner)) => {
+                let (function, argument) = inner.into_parts();
+                rest.push(Frame::Build);
+                rest.push(Frame::Visit(argument));
+                rest.push(Frame::Visit(function));
+            }
+            Frame::Build => {
+                let argument = done.pop().unwrap();
+                let function = done.pop().unwrap();
+                let mut node = Ast::Apply(AstInner::new(function, argument));
+                while let Some(rewritten) = rewrite(&node, identities) {
+                    node = rewritten;
+                    *changed = true;
+                }
+                done.push(node);
+            }
+        }
+    }
+    done.pop().unwrap()
+}
+
+fn rewrite(ast: &Ast, identities: &[(Term, Term)]) -> Option<Ast> {
+    identities.iter().find_map(|(lhs, rhs)| {
+        let mut bindings = HashMap::new();
+        if matches(lhs, ast, &mut bindings) {
+            Some(instantiate(rhs, &bindings))
+        } else {
+            None
+        }
+    })
+}
+
+fn matches<'a>(pattern: &Term, ast: &'a Ast, bindings: &mut HashMap<usize, &'a Ast>) -> bool {
+    match (pattern, ast) {
+        (Term::Var(n), _) => match bindings.get(n) {
+            Some(bound) => *bound == ast,
+            None => {
+                bindings.insert(*n, ast);
+                true
+            }
+        },
+        (Term::Atom(p), Ast::Leaf(a)) => p == a,
+        (Term::Apply(function, argument), Ast::Apply(inner)) => {
+            matches(function, inner.function(), bindings)
+                && matches(argument, inner.argument(), bindings)
+        }
+        _ => false,
+    }
+}
+
+fn instantiate(pattern: &Term, bindings: &HashMap<usize, &Ast>) -> Ast {
+    match pattern {
+        Term::Var(n) => bindings[n].clone(),
+        Term::Atom(atom) => Ast::Leaf(*atom),
+        Term::Apply(function, argument) => Ast::Apply(AstInner::new(
+            instantiate(function, bindings),
+            instantiate(argument, bindings),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbolic::equivalent;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn catalog_is_tested() {
+        for (lhs, rhs) in IDENTITIES {
+            let lhs = Term::try_from(*lhs).unwrap();
+            let rhs = Term::try_from(*rhs).unwrap();
+            assert!(rhs.size() < lhs.size(), "{} -> {}", lhs, rhs);
+            assert_eq!(
+                equivalent(&lhs, &rhs, 3, 100),
+                Some(true),
+                "{} -> {}",
+                lhs,
+                rhs
+            );
+        }
+    }
+
+    #[test]
+    fn optimize_terms() {
+        let result = optimize(ast("``skk"));
+        assert_eq!(
+            result,
+            Optimized {
+                ast: ast("i"),
+                before: 3,
+                after: 1
+            }
+        );
+
+        // ``s`kx`ky -> `k`xy
+        let result = optimize(ast("``s`k`ss`k`kk"));
+        assert_eq!(result.ast, ast("`k``ss`kk"));
+        assert_eq!((result.before, result.after), (7, 5));
+
+        // 書き換えで新しく現れた redex も書き換える: ``s`ki`ki -> `i`ki -> `ki
+        let result = optimize(ast("``s`ki`ki"));
+        assert_eq!(result.ast, ast("`ki"));
+
+        // 書き換えられないものはそのまま
+        let result = optimize(ast("``sii"));
+        assert_eq!(
+            result,
+            Optimized {
+                ast: ast("``sii"),
+                before: 3,
+                after: 3
+            }
+        );
+    }
+
+    #[test]
+    fn optimize_is_extensional() {
+        for s in &["```sk`kss", "``s`k`s`ki`ki", "```s``s`kski`ks"] {
+            let before = ast(s);
+            let result = optimize(before.clone());
+            assert!(result.after <= result.before);
+            assert_eq!(
+                equivalent(&Term::from(before), &Term::from(result.ast), 3, 100),
+                Some(true),
+                "{}",
+                s
+            );
+        }
+    }
+}
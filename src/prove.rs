@@ -0,0 +1,112 @@
+//! 含意だけの命題論理の証明を、型の付いた S, K, I の項として探す (Curry–Howard 対応)
+
+use crate::ast::Ast;
+use crate::enumerate;
+use crate::reduce::{self, Rule};
+use crate::types::{self, Type};
+
+/// `prove` の結果
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Proof {
+    /// 主要型が式そのものか、式がその代入例になっている最小の項
+    Found(Ast),
+    /// 古典論理でも恒真でないので、証明はない
+    Refuted,
+    /// 葉の数 `max_leaves` 以下には見つからなかった
+    NotFound { max_leaves: usize },
+}
+
+/// 型が `formula` になる閉じた項を、葉の少ないものから順に探す。
+///
+/// 型の付かない項と、K, I の redex を含む項は調べない。
+/// 簡約しても型は保たれ、K, I の簡約では項が小さくなるので、最小の証明はそれらを含まない。
+pub fn prove(formula: &Type, max_leaves: usize) -> Proof {
+    if !classically_valid(formula) {
+        return Proof::Refuted;
+    }
+    for leaves in 1..=max_leaves {
+        for candidate in enumerate::terms(leaves) {
+            if reduce::redexes(&candidate)
+                .iter()
+                .any(|redex| redex.rule != Rule::S)
+            {
+                continue;
+            }
+            if let Ok(principal) = types::infer(&candidate) {
+                if formula.is_instance_of(&principal) {
+                    return Proof::Found(candidate);
+                }
+            }
+        }
+    }
+    Proof::NotFound { max_leaves }
+}
+
+// 真理値表で調べる。型変数が多すぎるときは調べずに真とする
+fn classically_valid(formula: &Type) -> bool {
+    fn value(formula: &Type, variables: &[usize], assignment: u32) -> bool {
+        match formula {
+            Type::Var(n) => {
+                let index = variables.iter().position(|v| v == n).unwrap();
+                assignment >> index & 1 == 1
+            }
+            Type::Arrow(from, to) => {
+                !value(from, variables, assignment) || value(to, variables, assignment)
+            }
+        }
+    }
+
+    let variables = formula.variables();
+    if variables.len() > 16 {
+        return true;
+    }
+    (0..1u32 << variables.len()).all(|assignment| value(formula, &variables, assignment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn prove_str(s: &str, max_leaves: usize) -> Proof {
+        prove(&Type::try_from(s).unwrap(), max_leaves)
+    }
+
+    fn found(s: &str) -> Proof {
+        Proof::Found(Ast::try_from(s).unwrap())
+    }
+
+    #[test]
+    fn proves() {
+        assert_eq!(prove_str("p→p", 3), found("i"));
+        assert_eq!(prove_str("p→q→p", 3), found("k"));
+        assert_eq!(prove_str("(a→b→c)→(a→b)→a→c", 3), found("s"));
+        // 代入例でもよい
+        assert_eq!(prove_str("(p→p)→p→p", 3), found("i"));
+        assert_eq!(prove_str("p→q→q", 3), found("`ki"));
+        assert_eq!(prove_str("(b→c)→(a→b)→a→c", 4), found("``s`ksk"));
+    }
+
+    #[test]
+    fn found_proofs_have_the_type() {
+        let formula = Type::try_from("a→(a→b)→b").unwrap();
+        match prove(&formula, 5) {
+            Proof::Found(ast) => {
+                assert!(formula.is_instance_of(&types::infer(&ast).unwrap()));
+                assert_eq!(ast.size(), 5);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn unprovable() {
+        assert_eq!(prove_str("a→b", 5), Proof::Refuted);
+        assert_eq!(prove_str("(a→b)→b→a", 5), Proof::Refuted);
+        // パースの法則は古典論理では恒真だが直観主義論理では証明できない
+        assert_eq!(
+            prove_str("((a→b)→a)→a", 5),
+            Proof::NotFound { max_leaves: 5 }
+        );
+    }
+}
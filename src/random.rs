@@ -0,0 +1,212 @@
+//! シードから決定的に定まる乱択の項
+
+use crate::ast::{Ast, AstInner};
+use crate::token::Atom;
+
+const ATOMS: [Atom; 3] = [Atom::S, Atom::K, Atom::I];
+
+/// 生成する項の形の偏り
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Distribution {
+    /// 葉の数が同じ項の中から一様に選ぶ
+    Uniform,
+    /// 片側に偏った深い木
+    Deep,
+    /// 左右の釣り合った浅い木
+    Wide,
+}
+
+/// 葉の数が `size` の項を1つ作る。同じ引数に対しては常に同じ項を返す
+///
+/// `size` が0のときは panic する。
+pub fn random_term(seed: u64, size: usize, distribution: Distribution) -> Ast {
+    assert_ne!(size, 0);
+    let mut rng = Rng::new(seed);
+    match distribution {
+        Distribution::Uniform => remy(&mut rng, size),
+        Distribution::Deep => split(&mut rng, size, |rng, size| {
+            let near = 1 + rng.below(2);
+            if rng.below(2) == 0 {
+                near
+            } else {
+                size - near
+            }
+        }),
+        Distribution::Wide => split(&mut rng, size, |rng, size| {
+            let spread = size / 8;
+            size / 2 - spread + rng.below(2 * spread + 1)
+        }),
+    }
+}
+
+// SplitMix64
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // 0 以上 bound 未満
+    fn below(&mut self, bound: usize) -> usize {
+        ((self.next() as u128 * bound as u128) >> 64) as usize
+    }
+
+    fn atom(&mut self) -> Ast {
+        Ast::Leaf(ATOMS[self.below(3)])
+    }
+}
+
+// 葉の数が size の部分木を、関数側の葉の数を choose で決めながら作る
+fn split(rng: &mut Rng, size: usize, choose: impl Fn(&mut Rng, usize) -> usize) -> Ast {
+    enum Frame {
+        Generate(usize),
+        Build,
+    }
+
+    let mut rest = vec![Frame::Generate(size)];
+    let mut done = Vec::new();
+    while let Some(frame) = rest.pop() {
+        match frame {
+            Frame::Generate(1) => done.push(rng.atom()),
+            Frame::Generate(size) => {
+                let left = choose(rng, size).clamp(1, size - 1);
+                rest.push(Frame::Build);
+                rest.push(Frame::Generate(size - left));
+                rest.push(Frame::Generate(left));
+            }
+            Frame::Build => {
+                let argument = done.pop().unwrap();
+                let function = done.pop().unwrap();
+                done.push(Ast::Apply(AstInner::new(function, argument)));
+            }
+        }
+    }
+    done.pop().unwrap()
+}
+
+// Rémy の方法で一様な二分木を作り、葉に一様に原子コンビネータを置く
+fn remy(rng: &mut Rng, size: usize) -> Ast {
+    // children[n] が None ならば葉
+    let mut children: Vec<Option<(usize, usize)>> = vec![None];
+    let mut parent: Vec<Option<usize>> = vec![None];
+    let mut root = 0;
+    for _ in 1..size {
+        let target = rng.below(children.len());
+        let leaf = children.len();
+        let node = leaf + 1;
+        children.push(None);
+        parent.push(Some(node));
+        children.push(Some(if rng.below(2) == 0 {
+            (target, leaf)
+        } else {
+            (leaf, target)
+        }));
+        parent.push(parent[target]);
+        match parent[target] {
+            None => root = node,
+            Some(p) => {
+                let (f, a) = children[p].unwrap();
+                children[p] = Some(if f == target { (node, a) } else { (f, node) });
+            }
+        }
+        parent[target] = Some(node);
+    }
+
+    enum Frame {
+        Visit(usize),
+        Build,
+    }
+
+    let mut rest = vec![Frame::Visit(root)];
+    let mut done = Vec::new();
+    while let Some(frame) = rest.pop() {
+        match frame {
+            Frame::Visit(n) => match children[n] {
+                None => done.push(rng.atom()),
+                Some((function, argument)) => {
+                    rest.push(Frame::Build);
+                    rest.push(Frame::Visit(argument));
+                    rest.push(Frame::Visit(function));
+                }
+            },
+            Frame::Build => {
+                let argument = done.pop().unwrap();
+                let function = done.pop().unwrap();
+                done.push(Ast::Apply(AstInner::new(function, argument)));
+            }
+        }
+    }
+    done.pop().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enumerate;
+    use std::collections::HashMap;
+
+    #[test]
+    fn deterministic() {
+        for distribution in &[
+            Distribution::Uniform,
+            Distribution::Deep,
+            Distribution::Wide,
+        ] {
+            for seed in 0..10 {
+                let ast = random_term(seed, 20, *distribution);
+                assert_eq!(ast.size(), 20);
+                assert_eq!(ast, random_term(seed, 20, *distribution));
+            }
+        }
+        assert_ne!(
+            random_term(1, 20, Distribution::Uniform),
+            random_term(2, 20, Distribution::Uniform)
+        );
+    }
+
+    #[test]
+    fn uniform() {
+        // 葉の数3の項は 2 * 27 = 54 個
+        let mut counts = HashMap::new();
+        for seed in 0..54_000 {
+            *counts
+                .entry(random_term(seed, 3, Distribution::Uniform))
+                .or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 54);
+        for ast in enumerate::terms(3) {
+            let n = counts[&ast];
+            assert!(800 < n && n < 1200, "{:?}: {}", ast, n);
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        let total = |distribution| {
+            (0..20)
+                .map(|seed| random_term(seed, 200, distribution).depth())
+                .sum::<usize>()
+        };
+        let deep = total(Distribution::Deep);
+        let uniform = total(Distribution::Uniform);
+        let wide = total(Distribution::Wide);
+        assert!(
+            deep > uniform && uniform > wide,
+            "{} {} {}",
+            deep,
+            uniform,
+            wide
+        );
+        assert_eq!(random_term(0, 1, Distribution::Deep).size(), 1);
+        assert_eq!(random_term(0, 2, Distribution::Wide).size(), 2);
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+
+use crate::ast::{Ast, AstInner};
+use crate::token::Atom;
+
+/// 簡約で発火した規則
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Rule {
+    /// ```` ```sxyz -> ``xz`yz ````
+    S,
+    /// ``` ``kxy -> x ```
+    K,
+    /// `` `ix -> x ``
+    I,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// 適用ノードからどちらの子へ降りるか
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Direction {
+    Function,
+    Argument,
+}
+
+/// 根から部分項までの道順。根自身は空の道順
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Default)]
+pub struct Path(Vec<Direction>);
+
+impl From<Vec<Direction>> for Path {
+    fn from(directions: Vec<Direction>) -> Self {
+        Path(directions)
+    }
+}
+
+/// 関数側を `f`、引数側を `a` として並べる。根は `ε`
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "ε");
+        }
+        for d in &self.0 {
+            match d {
+                Direction::Function => write!(f, "f")?,
+                Direction::Argument => write!(f, "a")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Path {
+    pub fn directions(&self) -> &[Direction] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// 項の中の簡約可能な位置とそこで発火する規則
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Redex {
+    pub path: Path,
+    pub rule: Rule,
+}
+
+/// 根が redex であればその規則を返す
+pub fn rule_at_root(ast: &Ast) -> Option<Rule> {
+    let mut args = 0;
+    let mut head = ast;
+    while let Ast::Apply(inner) = head {
+        head = inner.function();
+        args += 1;
+    }
+    match (head, args) {
+        (Ast::Leaf(Atom::I), 1) => Some(Rule::I),
+        (Ast::Leaf(Atom::K), 2) => Some(Rule::K),
+        (Ast::Leaf(Atom::S), 3) => Some(Rule::S),
+        _ => None,
+    }
+}
+
+/// 根の redex を縮約する。根が redex でなければ `None`
+pub fn contract(ast: &Ast) -> Option<(Rule, Ast)> {
+    let rule = rule_at_root(ast)?;
+    let mut args = Vec::new();
+    let mut head = ast;
+    while let Ast::Apply(inner) = head {
+        args.push(inner.argument());
+        head = inner.function();
+    }
+    // args は外側 (最後の引数) から順に並んでいる
+    let result = match rule {
+        Rule::I => args[0].clone(),
+        Rule::K => args[1].clone(),
+        Rule::S => {
+            let (x, y, z) = (args[2], args[1], args[0]);
+            Ast::Apply(AstInner::new(
+                Ast::Apply(AstInner::new(x.clone(), z.clone())),
+                Ast::Apply(AstInner::new(y.clone(), z.clone())),
+            ))
+        }
+    };
+    Some((rule, result))
+}
+
+/// 項に含まれる全ての redex を前順 (最左最外のものが先頭) で列挙する
+pub fn redexes(ast: &Ast) -> Vec<Redex> {
+    let mut found = Vec::new();
+    let mut rest = vec![(ast, Vec::new())];
+    while let Some((ast, path)) = rest.pop() {
+        if let Some(rule) = rule_at_root(ast) {
+            found.push(Redex {
+                path: Path(path.clone()),
+                rule,
+            });
+        }
+        if let Ast::Apply(inner) = ast {
+            let mut argument = path.clone();
+            argument.push(Direction::Argument);
+            rest.push((inner.argument(), argument));
+            let mut function = path;
+            function.push(Direction::Function);
+            rest.push((inner.function(), function));
+        }
+    }
+    found
+}
+
+/// `path` の位置の部分項を縮約した項を返す。その位置が redex でなければ `None`
+pub fn reduce_at(ast: &Ast, path: &Path) -> Option<(Rule, Ast)> {
+    let mut spine = Vec::with_capacity(path.len());
+    let mut target = ast;
+    for d in path.directions() {
+        match target {
+            Ast::Leaf(_) => return None,
+            Ast::Apply(inner) => {
+                spine.push((inner, *d));
+                target = match d {
+                    Direction::Function => inner.function(),
+                    Direction::Argument => inner.argument(),
+                };
+            }
+        }
+    }
+    let (rule, mut result) = contract(target)?;
+    while let Some((inner, d)) = spine.pop() {
+        result = match d {
+            Direction::Function => Ast::Apply(AstInner::new(result, inner.to_argument())),
+            Direction::Argument => Ast::Apply(AstInner::new(inner.to_function(), result)),
+        };
+    }
+    Some((rule, result))
+}
+
+/// 1ステップで到達できる全ての項を、redex の前順に返す
+pub fn reducts(ast: &Ast) -> Vec<(Redex, Ast)> {
+    redexes(ast)
+        .into_iter()
+        .filter_map(|redex| {
+            let (_, reduct) = reduce_at(ast, &redex.path)?;
+            Some((redex, reduct))
+        })
+        .collect()
+}
+
+/// 簡約する redex の選び方
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Strategy {
+    /// 最左最外。正規形があれば必ずそこに至る
+    Normal,
+    /// 最左最内。引数を先に簡約する `Stax` と同じ順序
+    Applicative,
+}
+
+/// `strategy` に従って次に簡約する redex を選ぶ。正規形ならば `None`
+pub fn select(ast: &Ast, strategy: Strategy) -> Option<Redex> {
+    match strategy {
+        Strategy::Normal => {
+            let mut rest = vec![(ast, Vec::new())];
+            while let Some((ast, path)) = rest.pop() {
+                if let Some(rule) = rule_at_root(ast) {
+                    return Some(Redex {
+                        path: Path(path),
+                        rule,
+                    });
+                }
+                if let Ast::Apply(inner) = ast {
+                    let mut argument = path.clone();
+                    argument.push(Direction::Argument);
+                    rest.push((inner.argument(), argument));
+                    let mut function = path;
+                    function.push(Direction::Function);
+                    rest.push((inner.function(), function));
+                }
+            }
+            None
+        }
+        // 帰り掛け順で最初の redex は、内側に redex を含まない最も左のもの
+        Strategy::Applicative => {
+            let mut rest = vec![(ast, Vec::new(), false)];
+            while let Some((ast, path, visited)) = rest.pop() {
+                match ast {
+                    Ast::Apply(inner) if !visited => {
+                        rest.push((ast, path.clone(), true));
+                        let mut argument = path.clone();
+                        argument.push(Direction::Argument);
+                        rest.push((inner.argument(), argument, false));
+                        let mut function = path;
+                        function.push(Direction::Function);
+                        rest.push((inner.function(), function, false));
+                    }
+                    _ => {
+                        if let Some(rule) = rule_at_root(ast) {
+                            return Some(Redex {
+                                path: Path(path),
+                                rule,
+                            });
+                        }
+                    }
+                }
+            }
+            None
+        }
+    }
+}
+
+/// `strategy` に従って1ステップ簡約する。正規形ならば `None`
+pub fn step(ast: &Ast, strategy: Strategy) -> Option<(Redex, Ast)> {
+    let redex = select(ast, strategy)?;
+    let (_, reduct) = reduce_at(ast, &redex.path)?;
+    Some((redex, reduct))
+}
+
+/// 簡約の1ステップ
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct Step {
+    /// 1から始まる通し番号
+    pub number: u64,
+    pub redex: Redex,
+    /// 簡約した後の項
+    pub term: Ast,
+}
+
+/// 正規形に至るまでの簡約を1ステップずつ返す
+pub struct Trace {
+    term: Ast,
+    strategy: Strategy,
+    number: u64,
+}
+
+impl Trace {
+    pub fn new(term: Ast, strategy: Strategy) -> Self {
+        Trace {
+            term,
+            strategy,
+            number: 0,
+        }
+    }
+
+    /// 最後に返したステップの後の項
+    pub fn term(&self) -> &Ast {
+        &self.term
+    }
+}
+
+impl Iterator for Trace {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (redex, term) = step(&self.term, self.strategy)?;
+        self.term = term.clone();
+        self.number += 1;
+        Some(Step {
+            number: self.number,
+            redex,
+            term,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn contract_root() {
+        assert_eq!(contract(&ast("`is")), Some((Rule::I, ast("s"))));
+        assert_eq!(contract(&ast("``ksi")), Some((Rule::K, ast("s"))));
+        assert_eq!(contract(&ast("```skki")), Some((Rule::S, ast("``ki`ki"))));
+        assert_eq!(contract(&ast("`sk")), None);
+        assert_eq!(contract(&ast("`k`ii")), None);
+    }
+
+    #[test]
+    fn all_redexes() {
+        // ``k`ii`is: 根の K と、`ii と `is の I
+        let found = redexes(&ast("``k`ii`is"));
+        let paths: Vec<String> = found.iter().map(|r| r.path.to_string()).collect();
+        assert_eq!(paths, vec!["ε", "fa", "a"]);
+        assert_eq!(
+            found.iter().map(|r| r.rule).collect::<Vec<_>>(),
+            vec![Rule::K, Rule::I, Rule::I]
+        );
+    }
+
+    #[test]
+    fn all_reducts() {
+        let found: Vec<Ast> = reducts(&ast("``k`ii`is"))
+            .into_iter()
+            .map(|(_, reduct)| reduct)
+            .collect();
+        assert_eq!(found, vec![ast("`ii"), ast("``ki`is"), ast("``k`iis")]);
+        assert!(reducts(&ast("``sk`ks")).is_empty());
+    }
+
+    #[test]
+    fn strategies() {
+        // ``k`ii`is
+        let term = ast("``k`ii`is");
+        let normal = select(&term, Strategy::Normal).unwrap();
+        assert_eq!(
+            (normal.rule, normal.path.to_string()),
+            (Rule::K, "ε".into())
+        );
+        let applicative = select(&term, Strategy::Applicative).unwrap();
+        assert_eq!(
+            (applicative.rule, applicative.path.to_string()),
+            (Rule::I, "fa".into())
+        );
+        assert_eq!(select(&ast("``sk`ks"), Strategy::Normal), None);
+    }
+
+    #[test]
+    fn trace() {
+        // `ki を捨てるので、最左最外では Ω を簡約しない
+        let term = ast("``ki```sii``sii");
+        let steps: Vec<Step> = Trace::new(term.clone(), Strategy::Normal).collect();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].number, 1);
+        assert_eq!(steps[0].term, ast("i"));
+        let mut trace = Trace::new(term, Strategy::Applicative);
+        assert_eq!(trace.nth(9).map(|s| s.number), Some(10));
+
+        let steps: Vec<Ast> = Trace::new(ast("```sski"), Strategy::Applicative)
+            .map(|s| s.term)
+            .collect();
+        assert_eq!(steps, vec![ast("``si`ki")]);
+        let trace = Trace::new(ast("```skki"), Strategy::Normal);
+        assert_eq!(trace.last().map(|s| s.term), Some(ast("i")));
+    }
+}
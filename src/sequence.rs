@@ -1,182 +1,414 @@
 use std::collections::VecDeque;
 use std::convert::{From, TryFrom};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
 use std::ops::Add;
 
 use crate::ast::Ast;
+use crate::diagnostic::Span;
 use crate::error::{FormulaError, LexiconError};
-use crate::token::Token;
+use crate::token::{Atom, Token};
+
+// 1語に詰めるトークンの数
+const TOKENS_PER_WORD: usize = 32;
+
+// 各トークンの下位ビット
+const LOW: u64 = 0x5555_5555_5555_5555;
+
+// 2ビットの符号から Token へ。Apply が0なので、原子はどちらかのビットが立っている
+static TOKENS: [Token; 4] = [
+    Token::Apply,
+    Token::Atom(Atom::S),
+    Token::Atom(Atom::K),
+    Token::Atom(Atom::I),
+];
+
+static CHARS: [char; 4] = ['`', 's', 'k', 'i'];
+
+fn encode(token: Token) -> u64 {
+    match token {
+        Token::Apply => 0,
+        Token::Atom(Atom::S) => 1,
+        Token::Atom(Atom::K) => 2,
+        Token::Atom(Atom::I) => 3,
+    }
+}
 
 /// Unlambda-style SKI expression として有効なTokenの列。有効なUnlambda-style SKI formula はこの中に含まれる。
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
-pub struct Sequence(VecDeque<Token>);
+///
+/// 1トークンを2ビットで表し、64ビットの語に32個ずつ詰めて持つ。
+/// 前から取り出したトークンの分は `start` を進めるだけにして、溜まったら詰め直す。
+#[derive(Clone)]
+pub struct Sequence {
+    words: Vec<u64>,
+    // 有効なトークンは start 番目から end 番目の手前まで
+    start: usize,
+    end: usize,
+}
 
 impl From<Token> for Sequence {
     fn from(token: Token) -> Self {
-        Sequence(VecDeque::from(vec![token]))
+        let mut seq = Sequence::new();
+        seq.push_back(token);
+        seq
     }
 }
 
 impl From<VecDeque<Token>> for Sequence {
     fn from(inner: VecDeque<Token>) -> Self {
-        Sequence(inner)
+        inner.into_iter().collect()
     }
 }
 
 impl From<Vec<Token>> for Sequence {
     fn from(vec: Vec<Token>) -> Self {
-        Sequence(VecDeque::from(vec))
+        vec.into_iter().collect()
+    }
+}
+
+impl FromIterator<Token> for Sequence {
+    fn from_iter<T: IntoIterator<Item = Token>>(iter: T) -> Self {
+        let mut seq = Sequence::new();
+        for token in iter {
+            seq.push_back(token);
+        }
+        seq
     }
 }
 
 impl From<Ast> for Sequence {
     fn from(ast: Ast) -> Self {
-        match ast {
-            Ast::Leaf(leaf) => Sequence::from(Token::from(leaf)),
-            Ast::Apply(child) => {
-                Sequence::from(Token::a())
-                    + Sequence::from(child.to_function())
-                    + Sequence::from(child.to_argument())
+        let mut seq = Sequence::new();
+        let mut rest = vec![ast];
+        while let Some(ast) = rest.pop() {
+            match ast {
+                Ast::Leaf(leaf) => seq.push_back(Token::from(leaf)),
+                Ast::Apply(child) => {
+                    seq.push_back(Token::a());
+                    let (function, argument) = child.into_parts();
+                    rest.push(argument);
+                    rest.push(function);
+                }
             }
         }
+        seq
     }
 }
 
 impl TryFrom<&str> for Sequence {
     type Error = LexiconError;
 
+    /// `` ` ``, `s`, `k`, `i` 以外の文字 (空白を含む) があれば、その位置の `LexiconError`
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        let mut v = VecDeque::new();
-
-        for (i, c) in s.chars().enumerate() {
-            match c {
-                '`' => v.push_back(Token::a()),
-                's' => v.push_back(Token::s()),
-                'k' => v.push_back(Token::k()),
-                'i' => v.push_back(Token::i()),
-                _ => return Err(LexiconError::new(i as u32, c)),
+        lex(s, false)
+    }
+}
+
+// 前から読み、エラーの位置の行と列を数えておく
+fn lex(s: &str, skip_whitespace: bool) -> Result<Sequence, LexiconError> {
+    let mut seq = Sequence::new();
+    seq.words.reserve(s.len() / TOKENS_PER_WORD + 1);
+
+    let (mut line, mut column) = (1, 1);
+    for (offset, c) in s.char_indices() {
+        match c {
+            '`' => seq.push_back(Token::a()),
+            's' => seq.push_back(Token::s()),
+            'k' => seq.push_back(Token::k()),
+            'i' => seq.push_back(Token::i()),
+            '\n' if skip_whitespace => {
+                line += 1;
+                column = 0;
+            }
+            c if skip_whitespace && c.is_whitespace() => {}
+            _ => {
+                let span = Span {
+                    offset,
+                    line,
+                    column,
+                };
+                return Err(LexiconError::new(span, c));
             }
         }
-        Ok(Sequence::from(v))
+        column += 1;
     }
+    Ok(seq)
 }
 
 impl TryFrom<String> for Sequence {
     type Error = LexiconError;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        Ok(Sequence::try_from(s.as_str())?)
+        Sequence::try_from(s.as_str())
     }
 }
 
-impl Into<String> for Sequence {
-    fn into(self) -> String {
-        let mut string = String::new();
-
-        for t in self {
-            string = format!("{}{}", string, Into::<String>::into(t));
-        }
-        string
+impl From<Sequence> for String {
+    fn from(seq: Sequence) -> Self {
+        seq.render()
     }
 }
 
 impl Add for Sequence {
     type Output = Self;
 
-    fn add(self, arg: Self) -> Self::Output {
-        let mut v = VecDeque::new();
-        for t in self.0 {
-            v.push_back(t)
-        }
-        for t in arg.0 {
-            v.push_back(t)
-        }
-        Sequence::from(v)
+    fn add(mut self, arg: Self) -> Self::Output {
+        self.join(&arg);
+        self
         // この後argは使用不可…だよな?
     }
 }
 
+impl PartialEq for Sequence {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.codes().eq(other.codes())
+    }
+}
+
+impl Eq for Sequence {}
+
+impl Hash for Sequence {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for code in self.codes() {
+            state.write_u8(code as u8);
+        }
+    }
+}
+
+impl fmt::Debug for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Sequence").field(&self.render()).finish()
+    }
+}
+
 impl IntoIterator for Sequence {
     type Item = Token;
-    type IntoIter = std::collections::vec_deque::IntoIter<Token>;
+    type IntoIter = IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        IntoIter(self)
     }
 }
 
 // TODO: 何で動いているのか我々にも分からんのです
 impl<'a> IntoIterator for &'a Sequence {
     type Item = &'a Token;
-    type IntoIter = std::collections::vec_deque::Iter<'a, Token>;
+    type IntoIter = Iter<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.iter()
+        self.iter()
+    }
+}
+
+/// `Sequence` のトークンを前から取り出す
+pub struct IntoIter(Sequence);
+
+impl Iterator for IntoIter {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.dequeue()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl DoubleEndedIterator for IntoIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.pop()
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+/// `Sequence` のトークンへの参照を前から返す
+pub struct Iter<'a> {
+    seq: &'a Sequence,
+    front: usize,
+    back: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        let token = &TOKENS[self.seq.code(self.front) as usize];
+        self.front += 1;
+        Some(token)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.back - self.front, Some(self.back - self.front))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(&TOKENS[self.seq.code(self.back) as usize])
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence::new()
     }
 }
 
 impl Sequence {
+    /// `Sequence::try_from` と同じく読むが、空白と改行は読み飛ばす。複数行に分けて書いた項に使う
+    pub fn parse_skipping_whitespace(s: &str) -> Result<Self, LexiconError> {
+        lex(s, true)
+    }
+
     pub fn new() -> Self {
-        Sequence(VecDeque::new())
+        Sequence {
+            words: Vec::new(),
+            start: 0,
+            end: 0,
+        }
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// 原子 (s, k, i) の数。語ごとに数える
+    pub fn count_atoms(&self) -> usize {
+        self.atoms(self.start, self.end)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            seq: self,
+            front: self.start,
+            back: self.end,
+        }
+    }
+
+    /// 先頭から `index` 番目のトークン
+    pub fn get(&self, index: usize) -> Option<Token> {
+        if index < self.len() {
+            Some(TOKENS[self.code(self.start + index) as usize])
+        } else {
+            None
+        }
+    }
+
+    pub fn push_back(&mut self, token: Token) {
+        if self.end == self.words.len() * TOKENS_PER_WORD {
+            self.words.push(0);
+        }
+        let (word, shift) = position(self.end);
+        self.words[word] = self.words[word] & !(3 << shift) | encode(token) << shift;
+        self.end += 1;
     }
 
     pub fn pop(&mut self) -> Option<Token> {
-        self.0.pop_back()
+        if self.is_empty() {
+            return None;
+        }
+        self.end -= 1;
+        let token = TOKENS[self.code(self.end) as usize];
+        if self.is_empty() {
+            self.clear();
+        } else {
+            self.words.truncate(self.end.div_ceil(TOKENS_PER_WORD));
+        }
+        Some(token)
     }
 
     pub fn split(mut self, index: usize) -> (Sequence, Sequence) {
-        let t2 = self.0.split_off(index);
-        (self, Sequence::from(t2))
+        assert!(index <= self.len(), "index out of bounds");
+        let at = self.start + index;
+        let word = at / TOKENS_PER_WORD;
+        let rest = Sequence {
+            words: self.words[word..].to_vec(),
+            start: at - word * TOKENS_PER_WORD,
+            end: self.end - word * TOKENS_PER_WORD,
+        }
+        .normalized();
+        self.end = at;
+        self.words.truncate(at.div_ceil(TOKENS_PER_WORD));
+        (self.normalized(), rest)
     }
 
     pub fn dequeue(&mut self) -> Option<Token> {
-        self.0.pop_front()
+        if self.is_empty() {
+            return None;
+        }
+        let token = TOKENS[self.code(self.start) as usize];
+        self.start += 1;
+        // 読み終えた語が半分を超えたら詰め直す
+        let dead = self.start / TOKENS_PER_WORD;
+        if self.is_empty() {
+            self.clear();
+        } else if dead >= 64 && dead * 2 >= self.words.len() {
+            self.words.drain(..dead);
+            self.start -= dead * TOKENS_PER_WORD;
+            self.end -= dead * TOKENS_PER_WORD;
+        }
+        Some(token)
     }
 
     pub fn join(&mut self, seq: &Self) {
-        for t in seq {
-            self.0.push_back(*t)
+        for code in seq.codes() {
+            self.push_back(TOKENS[code as usize])
         }
     }
 
     pub fn is_valid(&self) -> Result<bool, FormulaError> {
-        let mut counter = 1;
-        for t in self {
-            match t {
-                Token::Apply => counter += 1,
-                Token::Atom(_) => counter -= 1,
-            }
-        }
-        if counter == 0 {
-            Ok(true)
-        } else if counter > 0 {
-            Err(FormulaError::NotEnoughAtoms)
-        } else {
-            // counter < 0
-            Err(FormulaError::SurplusTokens)
+        let atoms = self.atoms(self.start, self.end);
+        let applies = self.len() - atoms;
+        // 1 + applies - atoms
+        match (applies + 1).cmp(&atoms) {
+            std::cmp::Ordering::Equal => Ok(true),
+            std::cmp::Ordering::Greater => Err(FormulaError::NotEnoughAtoms),
+            std::cmp::Ordering::Less => Err(FormulaError::SurplusTokens),
         }
     }
 
     pub fn valid_point(&self) -> Result<usize, FormulaError> {
-        let mut counter = 1;
-        for (ord, t) in self.into_iter().enumerate() {
-            match t {
-                Token::Apply => counter += 1,
-                Token::Atom(_) => counter -= 1,
-            }
-            if counter == 0 {
-                return Ok(ord);
+        let mut counter: usize = 1;
+        let mut index = self.start;
+        while index < self.end {
+            let word_end = ((index / TOKENS_PER_WORD + 1) * TOKENS_PER_WORD).min(self.end);
+            let n = word_end - index;
+            if counter > n {
+                // この語の中では0にならないので、語ごとに数える
+                let atoms = self.atoms(index, word_end);
+                counter = counter + (n - atoms) - atoms;
+            } else {
+                for i in index..word_end {
+                    if self.code(i) == 0 {
+                        counter += 1;
+                    } else {
+                        counter -= 1;
+                    }
+                    if counter == 0 {
+                        return Ok(i - self.start);
+                    }
+                }
             }
+            index = word_end;
         }
-        if counter > 0 {
-            Err(FormulaError::NotEnoughAtoms)
-        } else {
-            // counter < 0
-            Err(FormulaError::SurplusTokens)
-        }
+        Err(FormulaError::NotEnoughAtoms)
     }
 
     pub fn cut_formula(self) -> Result<Self, FormulaError> {
@@ -190,6 +422,64 @@ impl Sequence {
         let ret = self.split(point);
         Ok(ret)
     }
+
+    fn clear(&mut self) {
+        self.words.clear();
+        self.start = 0;
+        self.end = 0;
+    }
+
+    fn normalized(mut self) -> Self {
+        if self.is_empty() {
+            self.clear();
+        }
+        self
+    }
+
+    // 絶対位置 index のトークンの符号
+    fn code(&self, index: usize) -> u64 {
+        let (word, shift) = position(index);
+        (self.words[word] >> shift) & 3
+    }
+
+    fn codes(&self) -> impl Iterator<Item = u64> + '_ {
+        (self.start..self.end).map(move |index| self.code(index))
+    }
+
+    // 絶対位置 from から to の手前までの原子の数を語ごとに数える
+    fn atoms(&self, from: usize, to: usize) -> usize {
+        if from >= to {
+            return 0;
+        }
+        let mut atoms = 0;
+        for word in from / TOKENS_PER_WORD..=(to - 1) / TOKENS_PER_WORD {
+            let base = word * TOKENS_PER_WORD;
+            let lo = from.max(base) - base;
+            let hi = to.min(base + TOKENS_PER_WORD) - base;
+            let mask = low_bits(2 * hi) & !low_bits(2 * lo);
+            let w = self.words[word];
+            atoms += ((w | w >> 1) & LOW & mask).count_ones() as usize;
+        }
+        atoms
+    }
+
+    fn render(&self) -> String {
+        self.codes().map(|code| CHARS[code as usize]).collect()
+    }
+}
+
+// トークンの絶対位置から、語の番号と語の中のビット位置
+fn position(index: usize) -> (usize, usize) {
+    (index / TOKENS_PER_WORD, index % TOKENS_PER_WORD * 2)
+}
+
+// 下位 bits ビットが立った語
+fn low_bits(bits: usize) -> u64 {
+    if bits >= 64 {
+        !0
+    } else {
+        (1 << bits) - 1
+    }
 }
 
 #[cfg(test)]
@@ -220,7 +510,25 @@ mod tests {
     #[test]
     fn from_str_invalid() {
         let string = "`ski-kk";
-        assert_eq!(Sequence::try_from(string), Err(LexiconError::new(4, '-')));
+        assert_eq!(
+            Sequence::try_from(string),
+            Err(LexiconError::new(Span::at(string, 4), '-'))
+        );
+    }
+
+    #[test]
+    fn from_str_whitespace() {
+        let seq = Sequence::parse_skipping_whitespace("``s k\n  i").unwrap();
+        assert_eq!(seq, Sequence::try_from("``ski").unwrap());
+        let source = "` s\n\t x";
+        let e = Sequence::parse_skipping_whitespace(source).unwrap_err();
+        assert_eq!((e.span(), e.error_char()), (Span::at(source, 6), 'x'));
+        assert_eq!(e.span().to_string(), "2:3");
+        // 既定では空白も使えない文字
+        assert_eq!(
+            Sequence::try_from("``s k"),
+            Err(LexiconError::new(Span::at("``s k", 3), ' '))
+        );
     }
 
     #[test]
@@ -339,4 +647,97 @@ mod tests {
         s.dequeue(); // will be ["s", "k"]
         assert_eq!(s.valid_point(), Ok(0));
     }
+
+    // トークンを1つずつ数える素朴な valid_point
+    fn naive_valid_point(tokens: &[Token]) -> Result<usize, FormulaError> {
+        let mut counter = 1;
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Apply => counter += 1,
+                Token::Atom(_) => counter -= 1,
+            }
+            if counter == 0 {
+                return Ok(i);
+            }
+        }
+        Err(FormulaError::NotEnoughAtoms)
+    }
+
+    #[test]
+    fn packed() {
+        // 語の境界をまたぐ長さで、素朴な実装と比べる
+        let mut state: u64 = 1;
+        for len in (0..200).chain([1000, 4097]) {
+            let tokens: Vec<Token> = (0..len)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    // Apply を少し多めにして、長い項も作る
+                    TOKENS[((state >> 33) % 7) as usize % 4]
+                })
+                .collect();
+            let mut seq = Sequence::from(tokens.clone());
+            assert_eq!(seq.len(), len);
+            assert_eq!(seq.iter().copied().collect::<Vec<_>>(), tokens);
+            assert_eq!(seq.clone().into_iter().rev().count(), len);
+            let string = String::from(seq.clone());
+            assert_eq!(Sequence::try_from(string.as_str()), Ok(seq.clone()));
+
+            for skip in 0..len.min(70) {
+                let rest = &tokens[skip..];
+                assert_eq!(seq.valid_point(), naive_valid_point(rest));
+                let atoms = rest.iter().filter(|t| **t != Token::Apply).count();
+                let expected = match (rest.len() - atoms + 1).cmp(&atoms) {
+                    std::cmp::Ordering::Equal => Ok(true),
+                    std::cmp::Ordering::Greater => Err(FormulaError::NotEnoughAtoms),
+                    std::cmp::Ordering::Less => Err(FormulaError::SurplusTokens),
+                };
+                assert_eq!(seq.is_valid(), expected);
+                assert_eq!(seq, Sequence::from(rest.to_vec()));
+                let (front, back) = seq.clone().split(rest.len() / 3);
+                assert_eq!(front + back, seq);
+                seq.dequeue();
+            }
+        }
+    }
+
+    #[test]
+    fn both_ends() {
+        let tokens: Vec<Token> = (0..10_000).map(|n| TOKENS[n % 4]).collect();
+        let mut seq = Sequence::from(tokens.clone());
+        let (mut front, mut back) = (0, tokens.len());
+        // 前から読み進めて詰め直しが起きても、後ろに足したものと順序が合う
+        while front < back {
+            assert_eq!(seq.dequeue(), Some(tokens[front]));
+            front += 1;
+            if front < back {
+                back -= 1;
+                assert_eq!(seq.pop(), Some(tokens[back]));
+            }
+            assert_eq!(seq.len(), back - front);
+            assert_eq!(
+                seq.get(0),
+                tokens.get(front).filter(|_| front < back).copied()
+            );
+        }
+        assert!(seq.is_empty());
+        assert_eq!(seq, Sequence::new());
+        seq.push_back(Token::i());
+        assert_eq!(String::from(seq), "i");
+
+        let hash = |seq: &Sequence| {
+            use std::collections::hash_map::DefaultHasher;
+            let mut hasher = DefaultHasher::new();
+            seq.hash(&mut hasher);
+            hasher.finish()
+        };
+        let mut shifted = Sequence::try_from("iii``sii").unwrap();
+        for _ in 0..3 {
+            shifted.dequeue();
+        }
+        let aligned = Sequence::try_from("``sii").unwrap();
+        assert_eq!(shifted, aligned);
+        assert_eq!(hash(&shifted), hash(&aligned));
+    }
 }
@@ -0,0 +1,230 @@
+//! `serde` feature を有効にしたときの `Serialize` / `Deserialize` 実装
+//!
+//! 形式は次の通り。
+//!
+//! * `Atom`: `"s"`, `"k"`, `"i"` のいずれかの文字列
+//! * `Token`: `Atom` の文字列か、適用を表す `` "`" ``
+//! * `Sequence`: Unlambda-style の文字列 (`"``sii"`)。well-formed でないものは読み込めない
+//! * `Ast`: 原子コンビネータは文字列、適用は `[関数, 引数]` の2要素の配列
+//!   (`[["s", "i"], "i"]`)。読み込み時は `Sequence` と同じ文字列形式も受け付ける
+//! * `Ski`: `Ast` と同じ形式。ただし `Ski` の値として表せる形
+//!   (`s`, `k`, `i`, `` `sx ``, `` `kx ``, ``` ``sxy ```) をしていなければ読み込めない
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use crate::ast::{Ast, AstInner};
+use crate::lambda::Apply;
+use crate::sequence::Sequence;
+use crate::term::Ski;
+use crate::token::{Atom, Token};
+
+impl Serialize for Atom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from(*self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Atom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Token::deserialize(deserializer)? {
+            Token::Atom(atom) => Ok(atom),
+            Token::Apply => Err(de::Error::invalid_value(
+                de::Unexpected::Str("`"),
+                &"one of \"s\", \"k\", \"i\"",
+            )),
+        }
+    }
+}
+
+impl Serialize for Token {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from(*self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Token {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "`" => Ok(Token::a()),
+            "s" => Ok(Token::s()),
+            "k" => Ok(Token::k()),
+            "i" => Ok(Token::i()),
+            _ => Err(de::Error::invalid_value(
+                de::Unexpected::Str(&s),
+                &"one of \"`\", \"s\", \"k\", \"i\"",
+            )),
+        }
+    }
+}
+
+impl Serialize for Sequence {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from(self.clone()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Sequence {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_sequence(&s)
+    }
+}
+
+fn parse_sequence<E: de::Error>(s: &str) -> Result<Sequence, E> {
+    let seq = Sequence::try_from(s).map_err(E::custom)?;
+    seq.is_valid().map_err(E::custom)?;
+    Ok(seq)
+}
+
+impl Serialize for Ast {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Ast::Leaf(atom) => atom.serialize(serializer),
+            Ast::Apply(inner) => {
+                let mut seq = serializer.serialize_seq(Some(2))?;
+                seq.serialize_element(inner.function())?;
+                seq.serialize_element(inner.argument())?;
+                seq.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Ast {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AstVisitor)
+    }
+}
+
+struct AstVisitor;
+
+impl<'de> Visitor<'de> for AstVisitor {
+    type Value = Ast;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "an atom string, a formula string or a [function, argument] array"
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+        let seq = parse_sequence(s)?;
+        Ast::try_from(seq).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let function = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let argument = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(3, &self));
+        }
+        Ok(Ast::Apply(AstInner::new(function, argument)))
+    }
+}
+
+impl Serialize for Ski {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Ast::from(self.clone()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ski {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ast = Ast::deserialize(deserializer)?;
+        ski_from_ast(&ast).ok_or_else(|| {
+            de::Error::custom(format!(
+                "{} is not a value of Ski",
+                String::from(Sequence::from(ast))
+            ))
+        })
+    }
+}
+
+// 簡約を行わずに形だけで Ski に変換する
+fn ski_from_ast(ast: &Ast) -> Option<Ski> {
+    let mut args = Vec::new();
+    let mut head = ast;
+    while let Ast::Apply(inner) = head {
+        args.push(inner.argument());
+        head = inner.function();
+    }
+    let head = match head {
+        Ast::Leaf(atom) => Ski::from(*atom),
+        Ast::Apply(_) => unreachable!(),
+    };
+    match (&head, args.len()) {
+        (_, 0) | (Ski::K, 1) | (Ski::S, 1) | (Ski::S, 2) => {}
+        _ => return None,
+    }
+    let mut ski = head;
+    while let Some(arg) = args.pop() {
+        ski = ski.apply(ski_from_ast(arg)?);
+    }
+    Some(ski)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn token() {
+        assert_eq!(serde_json::to_string(&Atom::S).unwrap(), "\"s\"");
+        assert_eq!(serde_json::to_string(&Token::a()).unwrap(), "\"`\"");
+        assert_eq!(serde_json::from_str::<Token>("\"k\"").unwrap(), Token::k());
+        assert!(serde_json::from_str::<Atom>("\"`\"").is_err());
+        assert!(serde_json::from_str::<Token>("\"x\"").is_err());
+    }
+
+    #[test]
+    fn sequence() {
+        let seq = Sequence::try_from("``sii").unwrap();
+        assert_eq!(serde_json::to_string(&seq).unwrap(), "\"``sii\"");
+        assert_eq!(serde_json::from_str::<Sequence>("\"``sii\"").unwrap(), seq);
+        // 字句としては正しいが well-formed でない
+        assert!(serde_json::from_str::<Sequence>("\"``si\"").is_err());
+        assert!(serde_json::from_str::<Sequence>("\"`sii\"").is_err());
+        assert!(serde_json::from_str::<Sequence>("\"`sx\"").is_err());
+    }
+
+    #[test]
+    fn nested_ast() {
+        let term = ast("``si`ki");
+        let json = serde_json::to_string(&term).unwrap();
+        assert_eq!(json, "[[\"s\",\"i\"],[\"k\",\"i\"]]");
+        assert_eq!(serde_json::from_str::<Ast>(&json).unwrap(), term);
+        assert_eq!(serde_json::from_str::<Ast>("\"``si`ki\"").unwrap(), term);
+        assert_eq!(
+            serde_json::from_str::<Ast>("[\"``sii\", \"i\"]").unwrap(),
+            ast("```siii")
+        );
+        assert!(serde_json::from_str::<Ast>("[\"s\"]").is_err());
+        assert!(serde_json::from_str::<Ast>("[\"s\", \"k\", \"i\"]").is_err());
+        assert!(serde_json::from_str::<Ast>("\"`s\"").is_err());
+    }
+
+    #[test]
+    fn ski() {
+        let term = Ski::S.apply(Ski::K).apply(Ski::I);
+        let json = serde_json::to_string(&term).unwrap();
+        assert_eq!(json, "[[\"s\",\"k\"],\"i\"]");
+        assert_eq!(serde_json::from_str::<Ski>(&json).unwrap(), term);
+        // 正規形でない項は Ski の値ではない
+        assert!(serde_json::from_str::<Ski>("\"`ii\"").is_err());
+        assert!(serde_json::from_str::<Ski>("\"```skii\"").is_err());
+    }
+}
@@ -1,20 +1,58 @@
+use std::time::Instant;
+
 use crate::ast::Ast;
-use crate::error::FormulaError;
-use crate::lambda::Apply;
+use crate::error::{EvalError, FormulaError};
+use crate::eval::{Config, Cycle};
+use crate::reduce::Rule;
 use crate::sequence::Sequence;
-use crate::term::Ski;
+use crate::stats::Stats;
+use crate::term::{Ski, SkiContainer1, SkiContainer2};
 use crate::token::{Atom, Token};
 
+// 止める指示と期限を確かめる間隔 (仕事の数)
+const INTERRUPT_INTERVAL: u64 = 1024;
+
+// programより先に処理する仕事。programと同じく末尾から取り出す
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+enum Task {
+    Push(Ski),
+    Apply,
+}
+
 pub struct Stax {
     program: Sequence,
+    pending: Vec<Task>,
+    stack: Vec<Ski>,
+    steps: u64,
+    max_depth: usize,
+    // program, 仕事, スタックを1つの項に戻したときの節点の数
+    nodes: usize,
+    // 規則の適用と部分適用で作った節点の数
+    allocations: u64,
+    // eval_with_stats で集める統計
+    stats: Option<Stats>,
+}
+
+// ループの検出で比べる機械の状態
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+struct State {
+    program: Sequence,
+    pending: Vec<Task>,
     stack: Vec<Ski>,
 }
 
 impl From<Sequence> for Stax {
     fn from(program: Sequence) -> Self {
+        let program_nodes = program.len();
         Stax {
             program,
+            pending: Vec::new(),
             stack: Vec::new(),
+            steps: 0,
+            max_depth: 0,
+            nodes: program_nodes,
+            allocations: 0,
+            stats: None,
         }
     }
 }
@@ -23,22 +61,96 @@ impl Stax {
     // programからargumentに全て移し終わった時にargumentに2つ以上残っていたらInvalidError::SurplusTokens
     // argumentからpopしようとしてできなかったならInvalidError::NotEnoughAtoms
     pub fn eval(&mut self) -> Result<Sequence, FormulaError> {
-        while let Some(top) = self.program.pop() {
-            match top {
-                Token::Atom(a) => match a {
-                    Atom::S => self.stack.push(Ski::from(Atom::S)),
-                    Atom::K => self.stack.push(Ski::from(Atom::K)),
-                    Atom::I => self.stack.push(Ski::from(Atom::I)),
-                },
-                Token::Apply => {
-                    let result = self.apply()?;
-                    self.stack.push(result);
-                }
-            }
+        match self.run(&Config::default()) {
+            Ok(seq) => Ok(seq),
+            Err(EvalError::FormulaError(e)) => Err(e),
+            Err(_) => unreachable!(),
         }
+    }
+
+    /// 規則 (S, K, I) の適用を `fuel` 回までに制限して評価する
+    pub fn eval_with_fuel(&mut self, fuel: u64) -> Result<Sequence, EvalError> {
+        self.eval_with(&Config::with_fuel(fuel))
+    }
+
+    /// `config` の制限の下で評価する。
+    /// ループの検出では、規則を適用する直前の program, 仕事, スタックの組を比べる
+    pub fn eval_with(&mut self, config: &Config) -> Result<Sequence, EvalError> {
+        self.run(config)
+    }
 
+    /// `eval_with` と同じく評価し、この呼び出しの間の統計と一緒に返す。
+    /// 統計はこの中でだけ集めるので、他の評価の分は含まない
+    pub fn eval_with_stats(&mut self, config: &Config) -> (Result<Sequence, EvalError>, Stats) {
+        let start = Instant::now();
+        let allocations = self.allocations;
+        self.stats = Some(Stats {
+            max_depth: self.stack.len(),
+            peak_size: self.atoms(),
+            ..Stats::default()
+        });
+        let result = self.run(config);
+        let mut stats = self.stats.take().unwrap();
+        stats.elapsed = start.elapsed();
+        stats.allocations = self.allocations - allocations;
+        stats.final_size = match &result {
+            Ok(seq) => seq.count_atoms(),
+            Err(_) => self.atoms(),
+        };
+        (result, stats)
+    }
+
+    /// これまでに適用した規則の回数
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// これまでのスタックの長さの最大値
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// program, 仕事, スタックを1つの項に戻したときの節点 (適用と原子) の数
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// 作った節点 (適用と原子) の数。S の規則で複製した引数の節点と、部分適用1つにつき適用の節点1つを数える。
+    /// `Config::max_allocations` と同じ単位
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// 値 `function` を値 `argument` に適用した結果を、規則の適用を後の仕事に回しながら求める。
+    /// スタックに積まれた値はそのまま残す
+    pub(crate) fn reduce(
+        &mut self,
+        function: Ski,
+        argument: Ski,
+        config: &Config,
+    ) -> Result<Ski, EvalError> {
+        // 外から渡された値も数え、節点の数を機械の状態と合わせておく
+        self.nodes += nodes(&function) + nodes(&argument) + 1;
+        self.pending.extend(vec![
+            Task::Apply,
+            Task::Push(function),
+            Task::Push(argument),
+        ]);
+        self.drain(config)?;
+        let value = self.pop()?;
+        self.nodes -= nodes(&value);
+        Ok(value)
+    }
+
+    fn run(&mut self, config: &Config) -> Result<Sequence, EvalError> {
+        self.drain(config)?;
+        self.finish()
+    }
+
+    // 仕事とprogramがなくなった後で、スタックに1つだけ残った値を結果にする
+    pub(crate) fn finish(&mut self) -> Result<Sequence, EvalError> {
         if self.stack.len() != 1 {
-            Err(FormulaError::SurplusTokens)
+            Err(FormulaError::SurplusTokens.into())
         } else {
             let res = self.stack.pop().unwrap();
             let ast = Ast::from(res);
@@ -47,12 +159,246 @@ impl Stax {
         }
     }
 
-    fn apply(&mut self) -> Result<Ski, FormulaError> {
-        let function = self.pop()?;
-        let argument = self.pop()?;
-        let ret = function.apply(argument);
+    // 仕事とprogramがなくなるまで進める。
+    // drain の後に外から仕事を足すと同じ状態から違う続きになりうるので、周期はこの中だけで探す
+    fn drain(&mut self, config: &Config) -> Result<(), EvalError> {
+        let mut cycle = Cycle::new();
+        let initial = if config.max_growth.is_some() {
+            self.size()
+        } else {
+            0
+        };
+        let mut ticks = 0;
+        loop {
+            if config.interruptible() && ticks % INTERRUPT_INTERVAL == 0 {
+                config.check_interrupt(self.steps, || self.snapshot())?;
+            }
+            ticks += 1;
+            let task = match self.next_task() {
+                Some(task) => task,
+                None => break,
+            };
+            self.process(task, config, &mut cycle, initial)?;
+        }
+        Ok(())
+    }
+
+    /// program か仕事から1つ取り出して処理する。残っていなければ `None`。
+    /// 値をスタックに積んだ (規則の適用で作った値を含む) ときは `Ok(true)`
+    pub(crate) fn step(&mut self) -> Option<Result<bool, EvalError>> {
+        let task = self.next_task()?;
+        let before = self.stack.len();
+        let result = self.process(task, &Config::default(), &mut Cycle::new(), 0);
+        // 積むと1つ増え、適用で値を作ると2つ取り出して1つ積む。S の規則は何も積まない
+        Some(result.map(|()| self.stack.len() + 1 >= before))
+    }
+
+    fn process(
+        &mut self,
+        task: Task,
+        config: &Config,
+        cycle: &mut Cycle<State>,
+        initial: usize,
+    ) -> Result<(), EvalError> {
+        match task {
+            Task::Push(ski) => {
+                self.push(ski);
+                if let Some(limit) = config.max_stack {
+                    if self.stack.len() > limit {
+                        return Err(EvalError::StackTooDeep { limit });
+                    }
+                }
+            }
+            Task::Apply => {
+                // 取り出した仕事を戻して、制限に引っかかっても続きから再開できるようにする
+                self.pending.push(Task::Apply);
+                self.check(config, cycle)?;
+                self.pending.pop();
+                self.apply();
+                if config.max_growth.is_some() {
+                    config.check_growth(initial, self.size())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // スタックの上2つを適用する前に、燃料、ループ、節点の数を確かめる
+    fn check(&self, config: &Config, cycle: &mut Cycle<State>) -> Result<(), EvalError> {
+        let fires = self.fires()?;
+        if fires && config.out_of_fuel(self.steps) {
+            return Err(EvalError::OutOfFuel);
+        }
+        if fires && config.detect_loops {
+            if let Some(cycle_length) = cycle.observe(&self.state()) {
+                return Err(EvalError::Diverges { cycle_length });
+            }
+        }
+        if config.max_nodes.is_none() && config.max_allocations.is_none() {
+            return Ok(());
+        }
+        // 引数を複製する前に、増える分を見積もる
+        let (added, allocated) = match &self.stack[self.stack.len() - 1] {
+            Ski::Spp(_) => {
+                let argument = &self.stack[self.stack.len() - 2];
+                let cloned = nodes(argument);
+                (cloned - 1, cloned as u64)
+            }
+            Ski::S | Ski::K | Ski::Sp(_) => (0, 1),
+            Ski::I | Ski::Kp(_) => (0, 0),
+        };
+        config.check_nodes(self.nodes + added)?;
+        config.check_allocations(self.allocations + allocated)
+    }
+
+    // 残りの仕事とスタックを1つの項に戻す。
+    // 後ろから読むので、program, 仕事, スタックの上から下の順に並べる
+    pub(crate) fn snapshot(&self) -> Sequence {
+        let mut seq = self.remaining();
+        for ski in self.stack.iter().rev() {
+            seq.join(&Sequence::from(Ast::from(ski.clone())));
+        }
+        seq
+    }
+
+    // まだ処理していない program と仕事。末尾から処理する
+    pub(crate) fn remaining(&self) -> Sequence {
+        let mut seq = self.program.clone();
+        for task in &self.pending {
+            match task {
+                Task::Push(ski) => seq.join(&Sequence::from(Ast::from(ski.clone()))),
+                Task::Apply => seq.push_back(Token::a()),
+            }
+        }
+        seq
+    }
+
+    // 下から順のスタック
+    pub(crate) fn stack(&self) -> &[Ski] {
+        &self.stack
+    }
+
+    // 機械の状態を1つの項に戻したときの原子の数
+    fn atoms(&self) -> usize {
+        self.nodes.div_ceil(2)
+    }
+
+    fn state(&self) -> State {
+        State {
+            program: self.program.clone(),
+            pending: self.pending.clone(),
+            stack: self.stack.clone(),
+        }
+    }
+
+    // 機械の状態に含まれる原子の数
+    fn size(&self) -> usize {
+        let pending = self.pending.iter().filter_map(|task| match task {
+            Task::Push(ski) => Some(ski),
+            Task::Apply => None,
+        });
+        self.program.count_atoms()
+            + self
+                .stack
+                .iter()
+                .chain(pending)
+                .map(Ski::size)
+                .sum::<usize>()
+    }
+
+    fn next_task(&mut self) -> Option<Task> {
+        if let Some(task) = self.pending.pop() {
+            return Some(task);
+        }
+        match self.program.pop()? {
+            Token::Atom(a) => match a {
+                Atom::S => Some(Task::Push(Ski::from(Atom::S))),
+                Atom::K => Some(Task::Push(Ski::from(Atom::K))),
+                Atom::I => Some(Task::Push(Ski::from(Atom::I))),
+            },
+            Token::Apply => Some(Task::Apply),
+        }
+    }
+
+    // スタックの上2つに対する適用が規則の適用になるか
+    fn fires(&self) -> Result<bool, FormulaError> {
+        if self.stack.len() < 2 {
+            return Err(FormulaError::NotEnoughAtoms);
+        }
+        Ok(matches!(
+            self.stack.last(),
+            Some(Ski::I) | Some(Ski::Kp(_)) | Some(Ski::Spp(_))
+        ))
+    }
 
-        Ok(ret)
+    // check でスタックに2つ以上あることは確かめてある
+    fn apply(&mut self) {
+        let function = self.stack.pop().unwrap();
+        let argument = self.stack.pop().unwrap();
+        match function {
+            // ```sxyz = ``xz`yz の計算を後の仕事に回し、Ski::apply の再帰を避ける
+            Ski::Spp(spp) => {
+                // 複製した引数の節点が増え、S の原子が1つ減る
+                let cloned = nodes(&argument);
+                self.nodes = self.nodes + cloned - 1;
+                self.allocations += cloned as u64;
+                self.fired(Rule::S, cloned as u64);
+                let (first, second) = spp.into_parts();
+                self.pending.extend(vec![
+                    Task::Apply,
+                    Task::Apply,
+                    Task::Push(first),
+                    Task::Push(argument.clone()),
+                    Task::Apply,
+                    Task::Push(second),
+                    Task::Push(argument),
+                ]);
+            }
+            Ski::I => {
+                self.nodes -= 2;
+                self.fired(Rule::I, 0);
+                self.push(argument);
+            }
+            Ski::Kp(kp) => {
+                self.nodes -= 3 + nodes(&argument);
+                self.fired(Rule::K, 0);
+                self.push(kp.into_inner());
+            }
+            Ski::S => self.construct(Ski::Sp(SkiContainer1::from(argument))),
+            Ski::K => self.construct(Ski::Kp(SkiContainer1::from(argument))),
+            Ski::Sp(sp) => {
+                self.construct(Ski::Spp(SkiContainer2::from((sp.into_inner(), argument))))
+            }
+        }
+    }
+
+    // 規則の適用を数える。統計を集めているときは、規則ごとの回数と項の大きさの最大値も記録する
+    fn fired(&mut self, rule: Rule, cloned: u64) {
+        self.steps += 1;
+        let atoms = self.atoms();
+        if let Some(stats) = &mut self.stats {
+            match rule {
+                Rule::S => stats.s += 1,
+                Rule::K => stats.k += 1,
+                Rule::I => stats.i += 1,
+            }
+            stats.cloned += cloned;
+            stats.peak_size = stats.peak_size.max(atoms);
+        }
+    }
+
+    // 部分適用の値を作る。適用の節点が値の中に移るだけなので、節点の数は変わらない
+    fn construct(&mut self, ski: Ski) {
+        self.allocations += 1;
+        self.push(ski);
+    }
+
+    fn push(&mut self, ski: Ski) {
+        self.stack.push(ski);
+        self.max_depth = self.max_depth.max(self.stack.len());
+        if let Some(stats) = &mut self.stats {
+            stats.max_depth = stats.max_depth.max(self.stack.len());
+        }
     }
 
     fn pop(&mut self) -> Result<Ski, FormulaError> {
@@ -63,10 +409,18 @@ impl Stax {
     }
 }
 
+// 値を `Ast` に直したときの節点 (適用と原子) の数
+fn nodes(ski: &Ski) -> usize {
+    2 * ski.size() - 1
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::eval::CancelToken;
     use std::convert::TryFrom;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn eval() {
@@ -88,4 +442,214 @@ mod tests {
 
         assert_eq!(result, Ok(target));
     }
+
+    #[test]
+    fn eval_invalid() {
+        let mut stax = Stax::from(Sequence::try_from("``si").unwrap());
+        assert_eq!(stax.eval(), Err(FormulaError::NotEnoughAtoms));
+        let mut stax = Stax::from(Sequence::try_from("`sii").unwrap());
+        assert_eq!(stax.eval(), Err(FormulaError::SurplusTokens));
+    }
+
+    #[test]
+    fn eval_with_fuel() {
+        // ```sski: S の1回だけ
+        let seq = Sequence::try_from("```sski").unwrap();
+        let mut stax = Stax::from(seq.clone());
+        assert_eq!(stax.eval_with_fuel(0), Err(EvalError::OutOfFuel));
+        let mut stax = Stax::from(seq);
+        assert_eq!(
+            stax.eval_with_fuel(1),
+            Ok(Sequence::try_from("``si`ki").unwrap())
+        );
+        assert_eq!(stax.steps(), 1);
+
+        let mut stax = Stax::from(Sequence::try_from("```sii``sii").unwrap());
+        assert_eq!(stax.eval_with_fuel(1000), Err(EvalError::OutOfFuel));
+        assert_eq!(stax.steps(), 1000);
+    }
+
+    #[test]
+    fn eval_with_config() {
+        let config = Config {
+            detect_loops: true,
+            ..Config::default()
+        };
+        let mut stax = Stax::from(Sequence::try_from("```sii``sii").unwrap());
+        assert_eq!(
+            stax.eval_with(&config),
+            Err(EvalError::Diverges { cycle_length: 3 })
+        );
+        assert!(stax.steps() < 20);
+        let mut stax = Stax::from(Sequence::try_from("```s`kski").unwrap());
+        assert_eq!(
+            stax.eval_with(&config),
+            Ok(Sequence::try_from("`s`ki").unwrap())
+        );
+
+        // M = S(SII)I は M M = M M M と増えていく
+        let config = Config {
+            detect_loops: true,
+            max_growth: Some(3),
+            fuel: Some(10_000),
+            ..Config::default()
+        };
+        let mut stax = Stax::from(Sequence::try_from("```s``siii``s``siii").unwrap());
+        assert!(matches!(
+            stax.eval_with(&config),
+            Err(EvalError::Grows { initial: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        // 正規形まで評価すると、節点の数は結果の長さと等しい
+        for s in &["```sski", "```s`kski", "```s``s`ksk``skkk", "``k`sii"] {
+            let mut stax = Stax::from(Sequence::try_from(*s).unwrap());
+            let output = stax.eval().unwrap();
+            assert_eq!(stax.nodes(), output.len(), "{}", s);
+            assert!(stax.allocations() > 0);
+        }
+
+        // 部分適用 `sk, ``skk, `ki で3つ、S の規則で `ki を複製して3つ、`k`ki を2回作って2つ
+        let mut stax = Stax::from(Sequence::try_from("```skk`ki").unwrap());
+        assert_eq!(stax.eval(), Ok(Sequence::try_from("`ki").unwrap()));
+        assert_eq!(stax.allocations(), 8);
+
+        // 外から渡した値の適用の後も、節点の数は機械の状態と合う
+        let mut stax = Stax::from(Sequence::new());
+        let sii = Ski::Spp(SkiContainer2::from((Ski::I, Ski::I)));
+        let value = stax.reduce(sii, Ski::K, &Config::default());
+        assert_eq!(value, Ok(Ski::Kp(SkiContainer1::from(Ski::K))));
+        assert_eq!(stax.nodes(), 0);
+
+        let growing = || Stax::from(Sequence::try_from("```s``siii``s``siii").unwrap());
+        let config = Config {
+            max_nodes: Some(100),
+            ..Config::default()
+        };
+        let mut stax = growing();
+        assert_eq!(
+            stax.eval_with(&config),
+            Err(EvalError::TooManyNodes { limit: 100 })
+        );
+        assert!(stax.nodes() <= 100);
+        let config = Config {
+            max_allocations: Some(1000),
+            ..Config::default()
+        };
+        let mut stax = growing();
+        assert_eq!(
+            stax.eval_with(&config),
+            Err(EvalError::TooManyAllocations { limit: 1000 })
+        );
+        assert!(stax.allocations() <= 1000);
+
+        // ``...`ki...i は評価の前に原子をスタックに積む
+        let n = 1000;
+        let deep = "`".repeat(n) + "k" + &"i".repeat(n);
+        let config = Config {
+            max_stack: Some(100),
+            ..Config::default()
+        };
+        let mut stax = Stax::from(Sequence::try_from(deep.as_str()).unwrap());
+        assert_eq!(
+            stax.eval_with(&config),
+            Err(EvalError::StackTooDeep { limit: 100 })
+        );
+        assert_eq!(stax.max_depth(), 101);
+        let mut stax = Stax::from(Sequence::try_from(deep.as_str()).unwrap());
+        assert_eq!(stax.eval(), Ok(Sequence::try_from("i").unwrap()));
+    }
+
+    #[test]
+    fn interrupts() {
+        // 別のスレッドから止める
+        let token = CancelToken::new();
+        let config = Config {
+            cancel: Some(token.clone()),
+            ..Config::default()
+        };
+        let handle = thread::spawn(move || {
+            let mut stax = Stax::from(Sequence::try_from("```sii``sii").unwrap());
+            (stax.eval_with(&config), stax.steps())
+        });
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+        match handle.join().unwrap() {
+            (Err(EvalError::Cancelled { steps, state }), total) => {
+                assert_eq!(steps, total);
+                assert_eq!(state.is_valid(), Ok(true));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // 途中の項から評価し直しても同じ正規形になる
+        let s = "```s``s`ksk``skkk";
+        let expected = Stax::from(Sequence::try_from(s).unwrap()).eval().unwrap();
+        let mut stax = Stax::from(Sequence::try_from(s).unwrap());
+        assert_eq!(stax.eval_with_fuel(2), Err(EvalError::OutOfFuel));
+        let config = Config {
+            deadline: Some(Instant::now()),
+            ..Config::default()
+        };
+        match stax.eval_with(&config) {
+            Err(EvalError::TimedOut { steps: 2, state }) => {
+                assert_eq!(Stax::from(state).eval(), Ok(expected.clone()))
+            }
+            other => panic!("{:?}", other),
+        }
+        // 止めても機械の状態は壊れないので、そのまま続けられる
+        assert_eq!(stax.eval(), Ok(expected));
+    }
+
+    #[test]
+    fn stats() {
+        let s = "```s``s`ksk``skkk";
+        let mut stax = Stax::from(Sequence::try_from(s).unwrap());
+        let (result, stats) = stax.eval_with_stats(&Config::default());
+        let output = result.unwrap();
+        assert_eq!(stats.steps(), stax.steps());
+        assert!(stats.s > 0 && stats.k > 0);
+        assert_eq!(stats.max_depth, stax.max_depth());
+        assert_eq!(stats.allocations, stax.allocations());
+        assert!(stats.cloned > 0 && stats.cloned <= stats.allocations);
+        assert_eq!(stats.final_size, output.count_atoms());
+        assert!(stats.peak_size >= 9);
+
+        // 途中で止めても、そこまでの統計を返す
+        let mut stax = Stax::from(Sequence::try_from("```sii``sii").unwrap());
+        let (result, stats) = stax.eval_with_stats(&Config::with_fuel(30));
+        assert_eq!(result, Err(EvalError::OutOfFuel));
+        assert_eq!((stats.s, stats.k, stats.i), (10, 0, 20));
+        assert_eq!((stats.peak_size, stats.final_size), (8, 6));
+
+        // 統計は呼び出しごとで、間に挟んだ eval_with の分は数えない
+        let allocations = stax.allocations();
+        assert_eq!(stax.eval_with_fuel(40), Err(EvalError::OutOfFuel));
+        let between = stax.allocations() - allocations;
+        let (result, stats) = stax.eval_with_stats(&Config::with_fuel(60));
+        assert_eq!(result, Err(EvalError::OutOfFuel));
+        assert_eq!((stats.steps(), stats.s, stats.i), (20, 6, 14));
+        assert_eq!(
+            stats.allocations,
+            stax.allocations() - allocations - between
+        );
+        assert_eq!(stats.final_size, stax.atoms());
+    }
+
+    #[test]
+    fn deep_argument() {
+        // S の規則で深い引数 `k`k...`ki を複製し、ループの検出でハッシュを取っても溢れない
+        let n = 300_000;
+        let program = "```sii".to_string() + &"`k".repeat(n) + "i";
+        let config = Config {
+            detect_loops: true,
+            max_nodes: Some(100_000_000),
+            ..Config::default()
+        };
+        let mut stax = Stax::from(Sequence::try_from(program.as_str()).unwrap());
+        let output = stax.eval_with(&config).unwrap();
+        assert_eq!(output.len(), 2 * (n - 1) + 1);
+    }
 }
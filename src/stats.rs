@@ -0,0 +1,49 @@
+//! `Stax` による評価全体の統計
+
+use std::fmt;
+use std::time::Duration;
+
+/// `Stax::eval_with_stats` の1回の呼び出しで集める評価の統計。大きさは原子の数
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Default)]
+pub struct Stats {
+    /// S の規則を適用した回数
+    pub s: u64,
+    /// K の規則を適用した回数
+    pub k: u64,
+    /// I の規則を適用した回数
+    pub i: u64,
+    /// スタックの長さの最大値
+    pub max_depth: usize,
+    /// 途中の項の大きさの最大値
+    pub peak_size: usize,
+    /// 評価を終えた (止めた) ときの項の大きさ
+    pub final_size: usize,
+    /// 作った節点 (適用と原子) の数。`Stax::allocations` と同じ
+    pub allocations: u64,
+    /// S の規則で複製した節点の数。`allocations` に含まれる
+    pub cloned: u64,
+    /// 評価にかかった時間
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// 規則を適用した回数の合計
+    pub fn steps(&self) -> u64 {
+        self.s + self.k + self.i
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "steps\t{}", self.steps())?;
+        writeln!(f, "s steps\t{}", self.s)?;
+        writeln!(f, "k steps\t{}", self.k)?;
+        writeln!(f, "i steps\t{}", self.i)?;
+        writeln!(f, "max stack depth\t{}", self.max_depth)?;
+        writeln!(f, "peak atoms\t{}", self.peak_size)?;
+        writeln!(f, "final atoms\t{}", self.final_size)?;
+        writeln!(f, "allocations\t{}", self.allocations)?;
+        writeln!(f, "cloned nodes\t{}", self.cloned)?;
+        write!(f, "elapsed\t{:?}", self.elapsed)
+    }
+}
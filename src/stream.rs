@@ -0,0 +1,467 @@
+//! `io::Read` から Unlambda 記法のトークンを少しずつ読み、前から順に評価する
+//!
+//! 入力全体を文字列にしないので、メモリに載らない大きさのファイルも評価できる。
+//! エラーの位置はストリームの先頭からのバイト単位。
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader, Bytes, Read};
+
+use crate::ast::Ast;
+use crate::error::{EvalError, FormulaError};
+use crate::eval::Config;
+use crate::sequence::Sequence;
+use crate::stack::Stax;
+use crate::term::Ski;
+use crate::token::Token;
+
+/// ストリームの読み込みと評価の失敗。`offset` はバイト単位
+#[derive(Debug)]
+pub enum StreamError {
+    /// トークンでも空白でもない文字。UTF-8 として読めないバイトは U+FFFD
+    Lexicon {
+        offset: u64,
+        error_char: char,
+    },
+    /// 原子が足りない (`offset` は入力の終わり) か、項の後にトークンが続いている
+    Formula {
+        offset: u64,
+        error: FormulaError,
+    },
+    /// `offset` のトークンを読んだところで規則の適用回数が上限に達した
+    OutOfFuel {
+        offset: u64,
+    },
+    /// `offset` のトークンを読んだところで、燃料以外の評価の制限に引っかかった。
+    /// `EvalError::Cancelled` と `EvalError::TimedOut` の `state` は、読んだところまでを評価した途中の列で、
+    /// 閉じていない適用を含む。その後に `offset` より後の入力を続けると、評価中の項全体になる
+    Eval {
+        offset: u64,
+        error: EvalError,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lexicon { offset, error_char } => {
+                write!(
+                    f,
+                    "unexpected character {:?} at byte {}",
+                    error_char, offset
+                )
+            }
+            Self::Formula { offset, error } => write!(f, "{} at byte {}", error, offset),
+            Self::OutOfFuel { offset } => write!(f, "out of fuel at byte {}", offset),
+            Self::Eval { offset, error } => write!(f, "{} at byte {}", error, offset),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for StreamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+/// `Read` からトークンとその開始位置 (バイト単位) を読む。空白と改行は読み飛ばす
+pub struct Tokens<R> {
+    bytes: Bytes<BufReader<R>>,
+    offset: u64,
+}
+
+impl<R: Read> Tokens<R> {
+    pub fn new(reader: R) -> Self {
+        Tokens {
+            bytes: BufReader::new(reader).bytes(),
+            offset: 0,
+        }
+    }
+
+    /// これまでに読んだバイト数
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.bytes.next() {
+            Some(byte) => {
+                let byte = byte?;
+                self.offset += 1;
+                Ok(Some(byte))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // 1バイト目を読んだ後で、UTF-8 の残りのバイトを読んで1文字にする
+    fn next_char(&mut self, first: u8) -> io::Result<char> {
+        let len = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            match self.next_byte()? {
+                Some(byte) => bytes.push(byte),
+                None => break,
+            }
+        }
+        Ok(match std::str::from_utf8(&bytes) {
+            Ok(s) => s.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER),
+            Err(_) => char::REPLACEMENT_CHARACTER,
+        })
+    }
+}
+
+impl<R: Read> Iterator for Tokens<R> {
+    type Item = Result<(u64, Token), StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let offset = self.offset;
+            let byte = match self.next_byte() {
+                Ok(Some(byte)) => byte,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let token = match byte {
+                b'`' => Token::a(),
+                b's' => Token::s(),
+                b'k' => Token::k(),
+                b'i' => Token::i(),
+                byte if byte.is_ascii_whitespace() => continue,
+                byte if byte.is_ascii() => {
+                    return Some(Err(StreamError::Lexicon {
+                        offset,
+                        error_char: byte as char,
+                    }))
+                }
+                byte => match self.next_char(byte) {
+                    Ok(c) if c.is_whitespace() => continue,
+                    Ok(error_char) => {
+                        return Some(Err(StreamError::Lexicon { offset, error_char }))
+                    }
+                    Err(e) => return Some(Err(e.into())),
+                },
+            };
+            return Some(Ok((offset, token)));
+        }
+    }
+}
+
+/// トークンを前から読みながら評価する。
+///
+/// 関数側が値になっている適用は、引数が値になった時点で適用する。
+/// 覚えておくのは閉じていない適用とその関数側の値だけで、読み終えた入力は持たない。
+pub struct Evaluator<R> {
+    tokens: Tokens<R>,
+    // 閉じていない適用。関数側の値が出来ていれば Some
+    open: Vec<Option<Ski>>,
+    stax: Stax,
+    config: Config,
+    max_open: usize,
+}
+
+impl<R: Read> Evaluator<R> {
+    pub fn new(reader: R) -> Self {
+        Evaluator {
+            tokens: Tokens::new(reader),
+            open: Vec::new(),
+            stax: Stax::from(Sequence::new()),
+            config: Config::default(),
+            max_open: 0,
+        }
+    }
+
+    /// 規則 (S, K, I) の適用を `fuel` 回までに制限する
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.config.fuel = Some(fuel);
+        self
+    }
+
+    /// 評価の制限をまとめて決める。ループの検出は、1つの適用の計算の中だけで行う
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// 入力を最後まで読んで評価する。項の後にトークンが続いていれば、そこで止めてエラーにする
+    pub fn eval(&mut self) -> Result<Sequence, StreamError> {
+        let mut done: Option<Ski> = None;
+        for next in self.tokens.by_ref() {
+            let (offset, token) = next?;
+            if done.is_some() {
+                return Err(StreamError::Formula {
+                    offset,
+                    error: FormulaError::SurplusTokens,
+                });
+            }
+            let mut value = match token {
+                Token::Apply => {
+                    self.open.push(None);
+                    self.max_open = self.max_open.max(self.open.len());
+                    continue;
+                }
+                Token::Atom(atom) => Ski::from(atom),
+            };
+            loop {
+                match self.open.pop() {
+                    None => {
+                        done = Some(value);
+                        break;
+                    }
+                    Some(None) => {
+                        self.open.push(Some(value));
+                        break;
+                    }
+                    Some(Some(function)) => {
+                        value = match self.stax.reduce(function, value, &self.config) {
+                            Ok(value) => value,
+                            Err(EvalError::FormulaError(error)) => {
+                                return Err(StreamError::Formula { offset, error })
+                            }
+                            Err(EvalError::OutOfFuel) => {
+                                return Err(StreamError::OutOfFuel { offset })
+                            }
+                            Err(EvalError::Cancelled { steps, state }) => {
+                                let state = self.read_so_far(state);
+                                let error = EvalError::Cancelled { steps, state };
+                                return Err(StreamError::Eval { offset, error });
+                            }
+                            Err(EvalError::TimedOut { steps, state }) => {
+                                let state = self.read_so_far(state);
+                                let error = EvalError::TimedOut { steps, state };
+                                return Err(StreamError::Eval { offset, error });
+                            }
+                            Err(error) => return Err(StreamError::Eval { offset, error }),
+                        };
+                    }
+                }
+            }
+        }
+        match done {
+            Some(value) => Ok(Sequence::from(Ast::from(value))),
+            None => Err(StreamError::Formula {
+                offset: self.tokens.offset(),
+                error: FormulaError::NotEnoughAtoms,
+            }),
+        }
+    }
+
+    // 閉じていない適用の開き括弧と関数側の値を外側から並べ、その後に計算中の適用 `current` を続ける
+    fn read_so_far(&self, current: Sequence) -> Sequence {
+        let mut seq = Sequence::new();
+        for function in &self.open {
+            seq.push_back(Token::a());
+            if let Some(function) = function {
+                seq.join(&Sequence::from(Ast::from(function.clone())));
+            }
+        }
+        seq.join(&current);
+        seq
+    }
+
+    /// これまでに適用した規則の回数
+    pub fn steps(&self) -> u64 {
+        self.stax.steps()
+    }
+
+    /// これまでの閉じていない適用の数の最大値
+    pub fn max_open(&self) -> usize {
+        self.max_open
+    }
+
+    /// これまでに読んだバイト数
+    pub fn offset(&self) -> u64 {
+        self.tokens.offset()
+    }
+}
+
+/// `reader` から読んだ項を評価する
+pub fn eval<R: Read>(reader: R) -> Result<Sequence, StreamError> {
+    Evaluator::new(reader).eval()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::CancelToken;
+    use std::convert::TryFrom;
+    use std::time::Instant;
+
+    fn seq(s: &str) -> Sequence {
+        Sequence::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<_> = Tokens::new("`s\n k".as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![(0, Token::a()), (1, Token::s()), (4, Token::k())]
+        );
+        // 位置は文字ではなくバイト単位
+        let mut tokens = Tokens::new("`\u{3000}sλ".as_bytes());
+        assert_eq!(tokens.nth(1).unwrap().unwrap(), (4, Token::s()));
+        match tokens.next() {
+            Some(Err(StreamError::Lexicon { offset, error_char })) => {
+                assert_eq!((offset, error_char), (5, 'λ'))
+            }
+            other => panic!("{:?}", other),
+        }
+        match Tokens::new(&[b'`', 0xff][..]).nth(1) {
+            Some(Err(StreamError::Lexicon { offset, error_char })) => {
+                assert_eq!((offset, error_char), (1, char::REPLACEMENT_CHARACTER))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn evaluates_like_stax() {
+        for s in &[
+            "i",
+            "```sski",
+            "``s`kki",
+            "```skss",
+            "``k`sii",
+            "```s``s`ksk``skkk",
+        ] {
+            let expected = Stax::from(seq(s)).eval().unwrap();
+            assert_eq!(eval(s.as_bytes()).unwrap(), expected, "{}", s);
+        }
+        let mut evaluator = Evaluator::new("```sski".as_bytes());
+        evaluator.eval().unwrap();
+        assert_eq!(evaluator.steps(), 1);
+        assert_eq!(evaluator.max_open(), 3);
+    }
+
+    #[test]
+    fn errors() {
+        match eval("``si\n".as_bytes()) {
+            Err(StreamError::Formula { offset, error }) => {
+                assert_eq!((offset, error), (5, FormulaError::NotEnoughAtoms))
+            }
+            other => panic!("{:?}", other),
+        }
+        match eval("`si k".as_bytes()) {
+            Err(StreamError::Formula { offset, error }) => {
+                assert_eq!((offset, error), (4, FormulaError::SurplusTokens))
+            }
+            other => panic!("{:?}", other),
+        }
+        match Evaluator::new("```sii``sii".as_bytes())
+            .with_fuel(50)
+            .eval()
+        {
+            Err(StreamError::OutOfFuel { offset }) => assert_eq!(offset, 10),
+            other => panic!("{:?}", other),
+        }
+        let config = Config {
+            detect_loops: true,
+            ..Config::default()
+        };
+        match Evaluator::new("```sii``sii".as_bytes())
+            .with_config(config)
+            .eval()
+        {
+            Err(StreamError::Eval { offset, error }) => {
+                assert_eq!(
+                    (offset, error),
+                    (10, EvalError::Diverges { cycle_length: 3 })
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+        // 信頼できない入力でも、節点の数を制限すればエラーで止まる
+        let config = Config {
+            max_nodes: Some(100),
+            ..Config::default()
+        };
+        match Evaluator::new("```s``siii``s``siii".as_bytes())
+            .with_config(config)
+            .eval()
+        {
+            Err(StreamError::Eval { offset, error }) => {
+                assert_eq!(
+                    (offset, error),
+                    (18, EvalError::TooManyNodes { limit: 100 })
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn interrupted() {
+        // 最初の適用 `si で期限切れになる。途中の列に残りの入力を続けると元の項に戻る
+        let config = Config {
+            deadline: Some(Instant::now()),
+            ..Config::default()
+        };
+        match Evaluator::new("``k```sii``siis".as_bytes())
+            .with_config(config)
+            .eval()
+        {
+            Err(StreamError::Eval {
+                offset,
+                error: EvalError::TimedOut { steps, state },
+            }) => {
+                assert_eq!((offset, steps), (7, 0));
+                assert_eq!(state, seq("``k```si"));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // 止めた列から評価し直しても、同じ正規形になる
+        let input = "``k```s``s`ksk``skkki";
+        let token = CancelToken::new();
+        token.cancel();
+        let config = Config {
+            cancel: Some(token),
+            ..Config::default()
+        };
+        match Evaluator::new(input.as_bytes()).with_config(config).eval() {
+            Err(StreamError::Eval {
+                offset,
+                error: EvalError::Cancelled { mut state, .. },
+            }) => {
+                state.join(&seq(&input[offset as usize + 1..]));
+                assert_eq!(
+                    Stax::from(state).eval(),
+                    Ok(eval(input.as_bytes()).unwrap())
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn long_stream() {
+        // `````...`ki...i を全て持たずに読む
+        let n = 1_000_000;
+        let reader = io::repeat(b'`')
+            .take(n)
+            .chain(&b"k"[..])
+            .chain(io::repeat(b'i').take(n));
+        let mut evaluator = Evaluator::new(reader);
+        assert_eq!(evaluator.eval().unwrap(), seq("i"));
+        assert_eq!(evaluator.offset(), 2 * n + 1);
+    }
+}
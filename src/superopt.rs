@@ -0,0 +1,129 @@
+//! 外延的に等しい最小の項の全探索
+
+use crate::ast::Ast;
+use crate::enumerate;
+use crate::optimize::optimize;
+use crate::reduce::{self, Rule};
+use crate::symbolic::{extensionally_equal, Term};
+
+/// `superoptimize` の結果
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Superoptimized {
+    /// これより小さい候補は全て外延的に等しくないことを確かめた
+    Shortest(Ast),
+    /// 見つかったが、より小さい候補の中に `fuel` 内で判定できなかったものがある
+    Unproven { ast: Ast, undecided: Vec<Ast> },
+    /// 大きさ `max_size` 以下の候補は全て外延的に等しくないことを確かめた
+    NotFound { max_size: usize },
+    /// 大きさ `max_size` 以下には見つからなかったが、判定できなかった候補がある
+    Undecided {
+        max_size: usize,
+        undecided: Vec<Ast>,
+    },
+}
+
+/// `target` と外延的に等しい項を、小さいものから順に探す。
+///
+/// 候補と `target` は `symbolic::extensionally_equal` で `fuel` 回までの簡約で比較する。
+/// `target` 自身が `fuel` 内で比較できなければ何も判定できないので `None`
+pub fn superoptimize(target: &Ast, max_size: usize, fuel: u64) -> Option<Superoptimized> {
+    let target_term = Term::from(target);
+    extensionally_equal(&target_term, &target_term, fuel)?;
+
+    let mut undecided = Vec::new();
+    for size in 1..=max_size {
+        for candidate in enumerate::terms(size) {
+            if !is_candidate(&candidate) {
+                continue;
+            }
+            match extensionally_equal(&target_term, &Term::from(&candidate), fuel) {
+                Some(true) if undecided.is_empty() => {
+                    return Some(Superoptimized::Shortest(candidate))
+                }
+                Some(true) => {
+                    return Some(Superoptimized::Unproven {
+                        ast: candidate,
+                        undecided,
+                    })
+                }
+                Some(false) => {}
+                None => undecided.push(candidate),
+            }
+        }
+    }
+    if undecided.is_empty() {
+        Some(Superoptimized::NotFound { max_size })
+    } else {
+        Some(Superoptimized::Undecided {
+            max_size,
+            undecided,
+        })
+    }
+}
+
+// K, I の redex を含むものや、恒等式でより小さくなるものには、
+// 外延的に等しくより小さい項があるので調べなくてよい
+fn is_candidate(ast: &Ast) -> bool {
+    let shrinking = reduce::redexes(ast)
+        .iter()
+        .any(|redex| redex.rule != Rule::S);
+    !shrinking && optimize(ast.clone()).after == ast.size()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::Applicative;
+    use std::convert::TryFrom;
+
+    fn ast(s: &str) -> Ast {
+        Ast::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn shortest() {
+        assert_eq!(
+            superoptimize(&ast("``skk"), 3, 100),
+            Some(Superoptimized::Shortest(ast("i")))
+        );
+        // S(KK)I = K
+        assert_eq!(
+            superoptimize(&ast("``s`kki"), 3, 100),
+            Some(Superoptimized::Shortest(ast("k")))
+        );
+        // ``s`ksk (合成 B) より小さいものはない
+        assert_eq!(
+            superoptimize(&ast("``s`ksk"), 4, 100),
+            Some(Superoptimized::Shortest(ast("``s`ksk")))
+        );
+        // 正規形の中の部分項だけが外延的に等しい。K(SKS) = KI = SK
+        assert_eq!(
+            superoptimize(&ast("`k``sks"), 3, 40),
+            Some(Superoptimized::Shortest(ast("`sk")))
+        );
+        // 大きくなり続ける候補も、項の大きさの上限で打ち切って比べ終える
+        assert_eq!(
+            superoptimize(&ast("``si`k``sks"), 6, 1000),
+            Some(Superoptimized::Shortest(ast("``si`sk")))
+        );
+    }
+
+    #[test]
+    fn not_found() {
+        assert_eq!(
+            superoptimize(&ast("``sii"), 2, 100),
+            Some(Superoptimized::NotFound { max_size: 2 })
+        );
+        // 正規形を持たない候補 (SII(SII) など) とは比べ終わらないので、見つからなくても証明にならない
+        let three = Ast::from(Applicative::try_from("S(S(KS)K)(S(S(KS)K)I)").unwrap());
+        assert!(matches!(
+            superoptimize(&three, 6, 1000),
+            Some(Superoptimized::Undecided { max_size: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn undecidable_target() {
+        assert_eq!(superoptimize(&ast("```sii``sii"), 3, 100), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! 自由変数を含む項と、それを使った外延的な比較
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::ast::Ast;
+use crate::error::NotationError;
+use crate::notation;
+use crate::token::Atom;
+
+/// 原子コンビネータと自由変数からなる項
+///
+/// 関数適用記法では小文字1文字が変数になる (`a` が `Var(0)`, `z` が `Var(25)`)。
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Term {
+    Atom(Atom),
+    Var(usize),
+    Apply(Box<Term>, Box<Term>),
+}
+
+impl From<Atom> for Term {
+    fn from(atom: Atom) -> Self {
+        Term::Atom(atom)
+    }
+}
+
+impl From<&Ast> for Term {
+    fn from(ast: &Ast) -> Self {
+        match ast {
+            Ast::Leaf(atom) => Term::Atom(*atom),
+            Ast::Apply(inner) => Term::from(inner.function()).apply(Term::from(inner.argument())),
+        }
+    }
+}
+
+impl From<Ast> for Term {
+    fn from(ast: Ast) -> Self {
+        Term::from(&ast)
+    }
+}
+
+impl TryFrom<&str> for Term {
+    type Error = NotationError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let leaf = |c: char| match c {
+            'S' => Some(Term::Atom(Atom::S)),
+            'K' => Some(Term::Atom(Atom::K)),
+            'I' => Some(Term::Atom(Atom::I)),
+            'a'..='z' => Some(Term::Var(c as usize - 'a' as usize)),
+            _ => None,
+        };
+        notation::parse_with(s, &leaf, &Term::apply, None)
+    }
+}
+
+/// 関数適用記法で、必要最小限の括弧を付けて表示する。26番目以降の変数は `v26` のように表示する
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Atom(atom) => write!(f, "{}", String::from(*atom).to_uppercase()),
+            Term::Var(n) if *n < 26 => write!(f, "{}", (b'a' + *n as u8) as char),
+            Term::Var(n) => write!(f, "v{}", n),
+            Term::Apply(function, argument) => match **argument {
+                Term::Apply(_, _) => write!(f, "{}({})", function, argument),
+                _ => write!(f, "{}{}", function, argument),
+            },
+        }
+    }
+}
+
+impl Term {
+    pub fn apply(self, argument: Term) -> Term {
+        Term::Apply(Box::new(self), Box::new(argument))
+    }
+
+    /// 葉 (原子コンビネータと変数) の個数
+    pub fn size(&self) -> usize {
+        match self {
+            Term::Atom(_) | Term::Var(_) => 1,
+            Term::Apply(function, argument) => function.size() + argument.size(),
+        }
+    }
+
+    /// 変数をどんな項にも合うものとしてパターンに使い、`ast` が合うかを調べる。
+    /// 同じ変数が2回以上現れるときは、同じ項に合わなければならない
+    pub fn matches(&self, ast: &Ast) -> bool {
+        fn go<'a>(pattern: &Term, ast: &'a Ast, bound: &mut HashMap<usize, &'a Ast>) -> bool {
+            match (pattern, ast) {
+                (Term::Var(n), _) => *bound.entry(*n).or_insert(ast) == ast,
+                (Term::Atom(a), Ast::Leaf(b)) => a == b,
+                (Term::Apply(function, argument), Ast::Apply(inner)) => {
+                    go(function, inner.function(), bound) && go(argument, inner.argument(), bound)
+                }
+                _ => false,
+            }
+        }
+        go(self, ast, &mut HashMap::new())
+    }
+}
+
+/// 簡約の途中で項がこれより大きく (葉が多く) なったら、正規形を求めるのをやめる
+pub const MAX_SIZE: usize = 10_000;
+
+/// 最左最外簡約で正規形を求める。
+/// `fuel` 回の簡約で正規形に至らないか、途中で項が `MAX_SIZE` を超えたら `None`
+pub fn normalize(term: Term, fuel: &mut u64) -> Option<Term> {
+    let (mut head, mut args) = head_normalize(term, fuel)?;
+    while let Some(argument) = args.pop() {
+        head = head.apply(normalize(argument, fuel)?);
+    }
+    Some(head)
+}
+
+// 頭が redex でなくなるまで簡約し、頭と引数 (末尾が最初の引数) に分ける
+fn head_normalize(term: Term, fuel: &mut u64) -> Option<(Term, Vec<Term>)> {
+    let mut size = term.size();
+    let mut head = term;
+    let mut args = Vec::new();
+    loop {
+        match head {
+            Term::Apply(function, argument) => {
+                args.push(*argument);
+                head = *function;
+                continue;
+            }
+            Term::Atom(Atom::I) if !args.is_empty() => {
+                spend(fuel)?;
+                size -= 1;
+                head = args.pop().unwrap();
+            }
+            Term::Atom(Atom::K) if args.len() >= 2 => {
+                spend(fuel)?;
+                head = args.pop().unwrap();
+                size -= 1 + args.pop().unwrap().size();
+            }
+            Term::Atom(Atom::S) if args.len() >= 3 => {
+                spend(fuel)?;
+                let x = args.pop().unwrap();
+                let y = args.pop().unwrap();
+                let z = args.pop().unwrap();
+                size += z.size() - 1;
+                if size > MAX_SIZE {
+                    return None;
+                }
+                args.push(y.apply(z.clone()));
+                args.push(z);
+                head = x;
+            }
+            _ => return Some((head, args)),
+        }
+    }
+}
+
+fn spend(fuel: &mut u64) -> Option<()> {
+    *fuel = fuel.checked_sub(1)?;
+    Some(())
+}
+
+/// 両者を `arity` 個の新しい変数に適用し、正規形が一致するかを調べる。
+///
+/// `Some(true)` ならば外延的に等しい。`Some(false)` は正規形が異なったことを表すだけで、
+/// 等しくないことの証明ではない (`SKS` と `I` は引数なしでは正規形が異なるが、外延的に等しい。
+/// 正規形の中の部分項が外延的に等しいだけのときは、引数を増やしても一致しない)。
+/// どちらかが `fuel` 回の簡約で正規形に至らなければ `None`
+pub fn equivalent(left: &Term, right: &Term, arity: usize, fuel: u64) -> Option<bool> {
+    let fresh = left.max_var().max(right.max_var()).map_or(0, |n| n + 1);
+    let saturate =
+        |term: &Term| (fresh..fresh + arity).fold(term.clone(), |term, n| term.apply(Term::Var(n)));
+    let mut fuel = fuel;
+    let left = normalize(saturate(left), &mut fuel)?;
+    let right = normalize(saturate(right), &mut fuel)?;
+    Some(left == right)
+}
+
+/// 外延的に (λ計算の βη 変換で) 等しいかを調べる。
+///
+/// 両者に同じ新しい変数を頭が変数になるまで適用し、頭の変数と引数の数を比べてから、
+/// 引数どうしを同じように比べる (η 展開した Böhm 木の比較)。
+/// `Some(false)` は頭の変数か引数の数が食い違う位置が見つかったことを表し、等しくないことの証明になる。
+/// 比べ終わる前に `fuel` 回の簡約を使い切るか、項が `MAX_SIZE` を超えたら `None`
+pub fn extensionally_equal(left: &Term, right: &Term, fuel: u64) -> Option<bool> {
+    let mut fresh = left.max_var().max(right.max_var()).map_or(0, |n| n + 1);
+    let mut fuel = fuel;
+    let mut rest = vec![(left.clone(), right.clone())];
+    while let Some((left, right)) = rest.pop() {
+        // 比べる組ごとにも燃料を使い、無限の Böhm 木でも止まるようにする
+        spend(&mut fuel)?;
+        let (mut left, mut right) = (left, right);
+        let (left_args, right_args) = loop {
+            let (left_head, left_args) = head_normalize(left, &mut fuel)?;
+            let (right_head, right_args) = head_normalize(right, &mut fuel)?;
+            match (&left_head, &right_head) {
+                (Term::Var(l), Term::Var(r)) if l != r || left_args.len() != right_args.len() => {
+                    return Some(false)
+                }
+                (Term::Var(_), Term::Var(_)) => break (left_args, right_args),
+                _ => {}
+            }
+            // 頭が原子コンビネータのままの方があれば、両方に新しい変数を1つ適用する
+            let variable = Term::Var(fresh);
+            fresh += 1;
+            left = rebuild(left_head, left_args).apply(variable.clone());
+            right = rebuild(right_head, right_args).apply(variable);
+        };
+        rest.extend(left_args.into_iter().zip(right_args));
+    }
+    Some(true)
+}
+
+fn rebuild(head: Term, mut args: Vec<Term>) -> Term {
+    let mut term = head;
+    while let Some(argument) = args.pop() {
+        term = term.apply(argument);
+    }
+    term
+}
+
+impl Term {
+    fn max_var(&self) -> Option<usize> {
+        match self {
+            Term::Atom(_) => None,
+            Term::Var(n) => Some(*n),
+            Term::Apply(function, argument) => function.max_var().max(argument.max_var()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(s: &str) -> Term {
+        Term::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn parse_and_print() {
+        assert_eq!(term("Sxy").to_string(), "Sxy");
+        assert_eq!(term("S(Kx)(Ky)").to_string(), "S(Kx)(Ky)");
+        assert_eq!(
+            term("ab"),
+            Term::Apply(Box::new(Term::Var(0)), Box::new(Term::Var(1)))
+        );
+        assert_eq!(Term::Var(30).to_string(), "v30");
+    }
+
+    #[test]
+    fn normal_form() {
+        let mut fuel = 10;
+        assert_eq!(normalize(term("SKKx"), &mut fuel), Some(term("x")));
+        assert_eq!(fuel, 8);
+        let mut fuel = 10;
+        assert_eq!(normalize(term("Sxyz"), &mut fuel), Some(term("xz(yz)")));
+        // 引数の中も簡約する
+        let mut fuel = 10;
+        assert_eq!(normalize(term("x(Iy)(KIzw)"), &mut fuel), Some(term("xyw")));
+        // SII(SII) は正規形を持たない
+        let mut fuel = 100;
+        assert_eq!(normalize(term("SII(SII)"), &mut fuel), None);
+    }
+
+    #[test]
+    fn extensional() {
+        let i = term("I");
+        assert_eq!(equivalent(&term("SKK"), &i, 1, 10), Some(true));
+        assert_eq!(equivalent(&term("SKS"), &i, 1, 10), Some(true));
+        assert_eq!(equivalent(&term("K"), &i, 1, 10), Some(false));
+        assert_eq!(equivalent(&term("SII(SII)"), &i, 0, 100), None);
+        // 自由変数と衝突しない変数に適用する
+        assert_eq!(equivalent(&term("Kx"), &term("Ky"), 1, 10), Some(false));
+        assert_eq!(equivalent(&term("S(Kx)I"), &term("x"), 1, 10), Some(true));
+    }
+
+    #[test]
+    fn extensional_equality() {
+        let equal = |l: &str, r: &str| extensionally_equal(&term(l), &term(r), 1000);
+        assert_eq!(equal("SKS", "I"), Some(true));
+        assert_eq!(equal("K(SKS)", "KI"), Some(true));
+        // 引数の中だけが外延的に等しい
+        assert_eq!(equal("SI(K(SKS))", "SI(KI)"), Some(true));
+        assert_eq!(
+            equivalent(&term("SI(K(SKS))"), &term("SI(KI)"), 3, 1000),
+            Some(false)
+        );
+        assert_eq!(equal("S(KK)I", "K"), Some(true));
+        assert_eq!(equal("K", "I"), Some(false));
+        assert_eq!(equal("SII", "I"), Some(false));
+        assert_eq!(equal("Kx", "Ky"), Some(false));
+        assert_eq!(equal("SII(SII)", "I"), None);
+        // 大きくなり続ける項は大きさの上限で打ち切る
+        assert_eq!(
+            extensionally_equal(&term("S(SII)I(S(SII)I)"), &term("I"), u64::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        let ast = |s: &str| Ast::try_from(s).unwrap();
+        assert!(term("Sxy").matches(&ast("``sk`ki")));
+        assert!(term("x").matches(&ast("```sii``sii")));
+        assert!(!term("Kx").matches(&ast("`sk")));
+        assert!(!term("Sxy").matches(&ast("`sk")));
+        // 同じ変数は同じ項に合う
+        assert!(term("Sxx").matches(&ast("``s`ki`ki")));
+        assert!(!term("Sxx").matches(&ast("``s`kik")));
+    }
+}
@@ -1,3 +1,6 @@
+use std::hash::{Hash, Hasher};
+use std::mem;
+
 use crate::lambda::{Apply, Eval, Lambda};
 use crate::token::Atom;
 
@@ -18,6 +21,18 @@ impl SkiContainer1 {
     pub fn get(&self) -> Ski {
         *self.content.clone()
     }
+
+    pub fn into_inner(mut self) -> Ski {
+        // Drop を実装しているので取り出す代わりに差し替える
+        mem::replace(&mut *self.content, Ski::I)
+    }
+}
+
+/// 深い項を再帰せずに破棄する
+impl Drop for SkiContainer1 {
+    fn drop(&mut self) {
+        drop_iteratively([&mut *self.content]);
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
@@ -43,9 +58,49 @@ impl SkiContainer2 {
     pub fn second(&self) -> Ski {
         *self.content_2.clone()
     }
+
+    pub fn into_parts(mut self) -> (Ski, Ski) {
+        (
+            mem::replace(&mut *self.content_1, Ski::I),
+            mem::replace(&mut *self.content_2, Ski::I),
+        )
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+/// 深い項を再帰せずに破棄する
+impl Drop for SkiContainer2 {
+    fn drop(&mut self) {
+        drop_iteratively([&mut *self.content_1, &mut *self.content_2]);
+    }
+}
+
+// 部分項を外に移してから1つずつ破棄する。移した後の部分項の子は原子なので再帰は浅い
+fn drop_iteratively<const N: usize>(children: [&mut Ski; N]) {
+    fn take(child: &mut Ski, rest: &mut Vec<Ski>) {
+        match child {
+            Ski::S | Ski::K | Ski::I => {}
+            _ => rest.push(mem::replace(child, Ski::I)),
+        }
+    }
+
+    let mut rest = Vec::new();
+    for child in children {
+        take(child, &mut rest);
+    }
+    while let Some(mut ski) = rest.pop() {
+        match &mut ski {
+            Ski::S | Ski::K | Ski::I => {}
+            Ski::Sp(c) | Ski::Kp(c) => take(&mut c.content, &mut rest),
+            Ski::Spp(c) => {
+                take(&mut c.content_1, &mut rest);
+                take(&mut c.content_2, &mut rest);
+            }
+        }
+    }
+}
+
+/// `Clone`, `PartialEq`, `Hash` は `Drop` と同じく再帰せずに部分項を辿るので、深い項でもスタックを溢れさせない
+#[derive(Debug)]
 pub enum Ski {
     S,
     K,
@@ -55,6 +110,91 @@ pub enum Ski {
     Spp(SkiContainer2),
 }
 
+impl Clone for Ski {
+    fn clone(&self) -> Self {
+        // 子を先に複製し、出来た値から親を組み立てる
+        enum Frame<'a> {
+            Visit(&'a Ski),
+            Sp,
+            Kp,
+            Spp,
+        }
+
+        let mut frames = vec![Frame::Visit(self)];
+        let mut values = Vec::new();
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(ski) => match ski {
+                    Ski::S => values.push(Ski::S),
+                    Ski::K => values.push(Ski::K),
+                    Ski::I => values.push(Ski::I),
+                    Ski::Sp(c) => frames.extend([Frame::Sp, Frame::Visit(&c.content)]),
+                    Ski::Kp(c) => frames.extend([Frame::Kp, Frame::Visit(&c.content)]),
+                    Ski::Spp(c) => frames.extend([
+                        Frame::Spp,
+                        Frame::Visit(&c.content_2),
+                        Frame::Visit(&c.content_1),
+                    ]),
+                },
+                Frame::Sp => {
+                    let content = values.pop().unwrap();
+                    values.push(Ski::Sp(SkiContainer1::from(content)));
+                }
+                Frame::Kp => {
+                    let content = values.pop().unwrap();
+                    values.push(Ski::Kp(SkiContainer1::from(content)));
+                }
+                Frame::Spp => {
+                    let second = values.pop().unwrap();
+                    let first = values.pop().unwrap();
+                    values.push(Ski::Spp(SkiContainer2::from((first, second))));
+                }
+            }
+        }
+        values.pop().unwrap()
+    }
+}
+
+impl PartialEq for Ski {
+    fn eq(&self, other: &Self) -> bool {
+        let mut rest = vec![(self, other)];
+        while let Some(pair) = rest.pop() {
+            match pair {
+                (Ski::S, Ski::S) | (Ski::K, Ski::K) | (Ski::I, Ski::I) => {}
+                (Ski::Sp(a), Ski::Sp(b)) | (Ski::Kp(a), Ski::Kp(b)) => {
+                    rest.push((&a.content, &b.content))
+                }
+                (Ski::Spp(a), Ski::Spp(b)) => {
+                    rest.push((&a.content_2, &b.content_2));
+                    rest.push((&a.content_1, &b.content_1));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Ski {}
+
+/// 前順に各節点の種類を書き込む。子の数は種類で決まるので、等しい項だけが同じ列になる
+impl Hash for Ski {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut rest = vec![self];
+        while let Some(ski) = rest.pop() {
+            mem::discriminant(ski).hash(state);
+            match ski {
+                Ski::S | Ski::K | Ski::I => {}
+                Ski::Sp(c) | Ski::Kp(c) => rest.push(&c.content),
+                Ski::Spp(c) => {
+                    rest.push(&c.content_2);
+                    rest.push(&c.content_1);
+                }
+            }
+        }
+    }
+}
+
 impl From<Atom> for Ski {
     fn from(a: Atom) -> Self {
         match a {
@@ -88,6 +228,43 @@ impl Apply for Ski {
     }
 }
 
+impl Ski {
+    /// `Ast` に直したときの葉 (原子コンビネータ) の個数
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        let mut rest = vec![self];
+        while let Some(ski) = rest.pop() {
+            size += 1;
+            match ski {
+                Ski::S | Ski::K | Ski::I => {}
+                Ski::Sp(c) | Ski::Kp(c) => rest.push(&c.content),
+                Ski::Spp(c) => {
+                    rest.push(&c.content_1);
+                    rest.push(&c.content_2);
+                }
+            }
+        }
+        size
+    }
+
+    /// `apply` と同じ計算を、規則 (S, K, I) の適用を `fuel` 回までに制限して行う。
+    /// 使い切ったら `None`
+    pub fn apply_with_fuel(&self, arg: Ski, fuel: &mut u64) -> Option<Ski> {
+        match self {
+            Ski::I | Ski::Kp(_) | Ski::Spp(_) => *fuel = fuel.checked_sub(1)?,
+            _ => {}
+        }
+        match self {
+            Ski::Spp(spp) => {
+                let eval_first = spp.first().apply_with_fuel(arg.clone(), fuel)?;
+                let eval_second = spp.second().apply_with_fuel(arg, fuel)?;
+                eval_first.apply_with_fuel(eval_second, fuel)
+            }
+            _ => Some(self.apply(arg)),
+        }
+    }
+}
+
 impl Eval for Ski {
     type Target = Ski;
 
@@ -106,4 +283,69 @@ impl Eval for Ski {
 impl Lambda for Ski {}
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::sequence::Sequence;
+    use std::convert::TryFrom;
+
+    const DEPTH: usize = 1_000_000;
+
+    #[test]
+    fn deep_conversions() {
+        // `k`k`k...`ki の形の深さ DEPTH の値
+        let mut ski = Ski::I;
+        for _ in 0..DEPTH {
+            ski = Ski::Kp(SkiContainer1::from(ski));
+        }
+        let ast = Ast::from(ski);
+        assert_eq!(ast.depth(), DEPTH);
+        let seq = Sequence::from(ast);
+        assert_eq!(seq.len(), 2 * DEPTH + 1);
+        let ast = Ast::try_from(seq).unwrap();
+        assert_eq!(ast.size(), DEPTH + 1);
+        drop(ast);
+
+        // 関数側に深い ```...sss と、Spp の入れ子
+        let mut ski = Ski::S;
+        for n in 0..DEPTH {
+            ski = match n % 2 {
+                0 => Ski::Sp(SkiContainer1::from(ski)),
+                _ => Ski::Spp(SkiContainer2::from((Ski::K, ski))),
+            };
+        }
+        drop(ski);
+    }
+
+    #[test]
+    fn deep_clone_eq_hash() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |ski: &Ski| {
+            let mut hasher = DefaultHasher::new();
+            ski.hash(&mut hasher);
+            hasher.finish()
+        };
+        let mut ski = Ski::I;
+        for n in 0..DEPTH {
+            ski = match n % 3 {
+                0 => Ski::Kp(SkiContainer1::from(ski)),
+                1 => Ski::Spp(SkiContainer2::from((ski, Ski::K))),
+                _ => Ski::Sp(SkiContainer1::from(ski)),
+            };
+        }
+        let copy = ski.clone();
+        assert_eq!(copy, ski);
+        assert_eq!(hash(&copy), hash(&ski));
+        assert_eq!(copy.size(), ski.size());
+        let other = Ski::Kp(SkiContainer1::from(copy));
+        assert_ne!(other, ski);
+        assert_ne!(hash(&other), hash(&ski));
+
+        // 子の順序も区別する
+        let a = Ski::Spp(SkiContainer2::from((Ski::S, Ski::K)));
+        let b = Ski::Spp(SkiContainer2::from((Ski::K, Ski::S)));
+        assert_ne!(a, b);
+        assert_ne!(hash(&a), hash(&b));
+    }
+}
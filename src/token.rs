@@ -6,12 +6,12 @@ pub enum Atom {
     I,
 }
 
-impl Into<String> for Atom {
-    fn into(self) -> String {
-        match self {
-            Self::S => String::from("s"),
-            Self::K => String::from("k"),
-            Self::I => String::from("i"),
+impl From<Atom> for String {
+    fn from(atom: Atom) -> Self {
+        match atom {
+            Atom::S => String::from("s"),
+            Atom::K => String::from("k"),
+            Atom::I => String::from("i"),
         }
     }
 }
@@ -29,11 +29,11 @@ impl From<Atom> for Token {
     }
 }
 
-impl Into<String> for Token {
-    fn into(self) -> String {
-        match self {
-            Self::Apply => String::from("`"),
-            Self::Atom(atom) => atom.into(),
+impl From<Token> for String {
+    fn from(token: Token) -> Self {
+        match token {
+            Token::Apply => String::from("`"),
+            Token::Atom(atom) => atom.into(),
         }
     }
 }
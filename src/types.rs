@@ -0,0 +1,653 @@
+//! 単純型の推論 (Hindley–Milner 風の単一化)
+//!
+//! 型の付く項は強正規化するので、探索で停止しない項を除くのに使える。
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+use crate::ast::Ast;
+use crate::error::NotationError;
+use crate::sequence::Sequence;
+use crate::token::Atom;
+
+/// 型変数と関数型からなる単純型
+///
+/// 型変数は現れた順に `a`, `b`, ... と表示し、27番目以降は `t26` のように表示する。
+/// 矢印は右結合で、必要な括弧だけを付ける。
+/// `Display` と `Drop` は再帰しないので、推論で出来た深い型でもスタックを溢れさせない
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum Type {
+    Var(usize),
+    Arrow(Box<Type>, Box<Type>),
+}
+
+// 部分型を外に移してから1つずつ破棄する。移した後の部分型の子は型変数なので再帰は浅い
+impl Drop for Type {
+    fn drop(&mut self) {
+        fn take(child: &mut Type, rest: &mut Vec<Type>) {
+            if let Type::Arrow(_, _) = child {
+                rest.push(mem::replace(child, Type::Var(0)));
+            }
+        }
+
+        let mut rest = Vec::new();
+        if let Type::Arrow(from, to) = self {
+            take(from, &mut rest);
+            take(to, &mut rest);
+        }
+        while let Some(mut ty) = rest.pop() {
+            if let Type::Arrow(from, to) = &mut ty {
+                take(from, &mut rest);
+                take(to, &mut rest);
+            }
+        }
+    }
+}
+
+// 深い型でもスタックを溢れさせないよう、書く順と逆に自前のスタックへ積む
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        enum Piece<'a> {
+            Type(&'a Type),
+            Text(&'static str),
+        }
+        let mut rest = vec![Piece::Type(self)];
+        while let Some(piece) = rest.pop() {
+            match piece {
+                Piece::Text(text) => write!(f, "{}", text)?,
+                Piece::Type(Type::Var(n)) if *n < 26 => write!(f, "{}", (b'a' + *n as u8) as char)?,
+                Piece::Type(Type::Var(n)) => write!(f, "t{}", n)?,
+                Piece::Type(Type::Arrow(from, to)) => {
+                    rest.push(Piece::Type(to));
+                    match **from {
+                        Type::Arrow(_, _) => {
+                            rest.extend([Piece::Text(")→"), Piece::Type(from), Piece::Text("(")])
+                        }
+                        Type::Var(_) => rest.extend([Piece::Text("→"), Piece::Type(from)]),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for Type {
+    type Error = NotationError;
+
+    /// `(a→b)→a→b` のような式を読む。矢印は `->` とも書ける。
+    /// 型変数の名前は `[a-z][a-z0-9_]*` で、現れた順に番号を振る。位置は文字単位
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            point: 0,
+            names: Vec::new(),
+        };
+        let ty = parser.arrow()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(ty),
+            Some(')') => Err(NotationError::UnmatchedParen(parser.point as u32)),
+            Some(c) => Err(NotationError::UnexpectedChar(parser.point as u32, c)),
+        }
+    }
+}
+
+impl Type {
+    pub fn arrow(self, to: Type) -> Type {
+        Type::Arrow(Box::new(self), Box::new(to))
+    }
+
+    /// 引数の個数。`a→b→c` なら2
+    pub fn arity(&self) -> usize {
+        let mut ty = self;
+        let mut arity = 0;
+        while let Type::Arrow(_, to) = ty {
+            ty = to;
+            arity += 1;
+        }
+        arity
+    }
+
+    /// `general` の型変数を置き換えて `self` にできるか。`self` の型変数は置き換えない
+    pub fn is_instance_of(&self, general: &Type) -> bool {
+        fn matches<'a>(
+            general: &Type,
+            specific: &'a Type,
+            substitution: &mut HashMap<usize, &'a Type>,
+        ) -> bool {
+            match (general, specific) {
+                (Type::Var(n), _) => *substitution.entry(*n).or_insert(specific) == specific,
+                (Type::Arrow(f1, t1), Type::Arrow(f2, t2)) => {
+                    matches(f1, f2, substitution) && matches(t1, t2, substitution)
+                }
+                (Type::Arrow(_, _), Type::Var(_)) => false,
+            }
+        }
+        matches(general, self, &mut HashMap::new())
+    }
+
+    /// 現れる型変数の番号を重複なく、現れた順に返す
+    pub fn variables(&self) -> Vec<usize> {
+        let mut variables = Vec::new();
+        let mut rest = vec![self];
+        while let Some(ty) = rest.pop() {
+            match ty {
+                Type::Var(n) if !variables.contains(n) => variables.push(*n),
+                Type::Var(_) => {}
+                Type::Arrow(from, to) => {
+                    rest.push(to);
+                    rest.push(from);
+                }
+            }
+        }
+        variables
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    point: usize,
+    names: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.point).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.point += 1;
+        }
+    }
+
+    // arrow := primary (('→' | "->") arrow)?
+    fn arrow(&mut self) -> Result<Type, NotationError> {
+        let from = self.primary()?;
+        self.skip_whitespace();
+        match self.peek() {
+            Some('→') => self.point += 1,
+            Some('-') if self.chars.get(self.point + 1) == Some(&'>') => self.point += 2,
+            _ => return Ok(from),
+        }
+        Ok(from.arrow(self.arrow()?))
+    }
+
+    fn primary(&mut self) -> Result<Type, NotationError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => {
+                let open = self.point;
+                self.point += 1;
+                let ty = self.arrow()?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(NotationError::UnclosedParen(open as u32));
+                }
+                self.point += 1;
+                Ok(ty)
+            }
+            Some('a'..='z') => {
+                let start = self.point;
+                while self
+                    .peek()
+                    .is_some_and(|c| matches!(c, 'a'..='z' | '0'..='9' | '_'))
+                {
+                    self.point += 1;
+                }
+                let name: String = self.chars[start..self.point].iter().collect();
+                let n = match self.names.iter().position(|known| *known == name) {
+                    Some(n) => n,
+                    None => {
+                        self.names.push(name);
+                        self.names.len() - 1
+                    }
+                };
+                Ok(Type::Var(n))
+            }
+            Some(')') | None => Err(NotationError::EmptyTerm(self.point as u32)),
+            Some(c) => Err(NotationError::UnexpectedChar(self.point as u32, c)),
+        }
+    }
+}
+
+/// 型が付かない理由。単純型では関数型同士の単一化しか起きないので、失敗は出現検査だけ
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct TypeError {
+    subterm: Ast,
+    variable: Type,
+    ty: Type,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "occurs check: cannot construct the infinite type {} = {} in {}",
+            self.variable,
+            self.ty,
+            String::from(Sequence::from(self.subterm.clone()))
+        )
+    }
+}
+
+impl Error for TypeError {}
+
+impl TypeError {
+    /// 単一化に失敗した適用
+    pub fn subterm(&self) -> &Ast {
+        &self.subterm
+    }
+
+    /// 自分自身を含む型と等しくなろうとした型変数
+    pub fn variable(&self) -> &Type {
+        &self.variable
+    }
+
+    pub fn ty(&self) -> &Type {
+        &self.ty
+    }
+}
+
+/// `ast` の主要型 (最も一般的な型) を求める
+///
+/// 単一化では出現検査をせず、循環した型ができたかを最後に1度だけ調べるので、型の大きさによらず
+/// ほぼ線形の時間で済む。型が付かないときは、初めて循環を作る適用を二分探索で探す
+pub fn infer(ast: &Ast) -> Result<Type, TypeError> {
+    let mut inference = Inference::default();
+    let root = match inference.term(ast, usize::MAX) {
+        Walk::Done(root) => root,
+        Walk::Stopped(..) => unreachable!(),
+    };
+    if inference.is_acyclic() {
+        return Ok(inference.resolve(root, &mut HashMap::new()));
+    }
+    Err(type_error(ast, inference.applications))
+}
+
+/// `ast` に型が付くか
+pub fn is_typable(ast: &Ast) -> bool {
+    let mut inference = Inference::default();
+    inference.term(ast, usize::MAX);
+    inference.is_acyclic()
+}
+
+// 全ての `applications` 個の適用を単一化すると循環する。後行順で初めて循環を作る適用を探し、
+// その直前の状態から出現検査をしながら単一化して、引っかかった型を報告する
+fn type_error(ast: &Ast, applications: usize) -> TypeError {
+    let cyclic = |limit| {
+        let mut inference = Inference::default();
+        inference.term(ast, limit);
+        !inference.is_acyclic()
+    };
+    // 最初の low 個では循環せず、最初の high 個では循環する
+    let (mut low, mut high) = (0, applications);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if cyclic(middle) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    let mut inference = Inference::default();
+    match inference.term(ast, low) {
+        Walk::Stopped(subterm, function, expected) => {
+            let (variable, ty) = inference
+                .unify_checked(function, expected)
+                .expect_err("the application makes a cycle");
+            let mut names = HashMap::new();
+            TypeError {
+                subterm: subterm.clone(),
+                variable: inference.resolve(variable, &mut names),
+                ty: inference.resolve(ty, &mut names),
+            }
+        }
+        Walk::Done(_) => unreachable!(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+    Var,
+    Arrow(usize, usize),
+    // 単一化で同じになった先
+    Link(usize),
+}
+
+// 型を節点の番号で共有しながら単一化する
+#[derive(Default)]
+struct Inference {
+    nodes: Vec<Node>,
+    // 単一化した適用の数
+    applications: usize,
+}
+
+// 項を辿った結果。`Stopped` は単一化する前の適用と、その関数の型と期待する型
+enum Walk<'a> {
+    Done(usize),
+    Stopped(&'a Ast, usize, usize),
+}
+
+impl Inference {
+    fn var(&mut self) -> usize {
+        self.nodes.push(Node::Var);
+        self.nodes.len() - 1
+    }
+
+    fn arrow(&mut self, from: usize, to: usize) -> usize {
+        self.nodes.push(Node::Arrow(from, to));
+        self.nodes.len() - 1
+    }
+
+    fn atom(&mut self, atom: Atom) -> usize {
+        let (a, b) = (self.var(), self.var());
+        match atom {
+            // (a→b→c)→(a→b)→a→c
+            Atom::S => {
+                let c = self.var();
+                let bc = self.arrow(b, c);
+                let abc = self.arrow(a, bc);
+                let ab = self.arrow(a, b);
+                let ac = self.arrow(a, c);
+                let rest = self.arrow(ab, ac);
+                self.arrow(abc, rest)
+            }
+            // a→b→a
+            Atom::K => {
+                let ba = self.arrow(b, a);
+                self.arrow(a, ba)
+            }
+            // a→a
+            Atom::I => self.arrow(a, a),
+        }
+    }
+
+    // 後行順に自前のスタックで辿り、`limit` 個の適用を単一化したら、次の適用の手前で止まる。
+    // 深い項でもスタックを溢れさせない
+    fn term<'a>(&mut self, ast: &'a Ast, limit: usize) -> Walk<'a> {
+        enum Frame<'a> {
+            Visit(&'a Ast),
+            Apply(&'a Ast),
+        }
+        let mut frames = vec![Frame::Visit(ast)];
+        let mut types = Vec::new();
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(Ast::Leaf(atom)) => {
+                    let ty = self.atom(*atom);
+                    types.push(ty);
+                }
+                Frame::Visit(ast @ Ast::Apply(inner)) => {
+                    frames.push(Frame::Apply(ast));
+                    frames.push(Frame::Visit(inner.argument()));
+                    frames.push(Frame::Visit(inner.function()));
+                }
+                Frame::Apply(ast) => {
+                    let argument = types.pop().unwrap();
+                    let function = types.pop().unwrap();
+                    let result = self.var();
+                    let expected = self.arrow(argument, result);
+                    if self.applications == limit {
+                        return Walk::Stopped(ast, function, expected);
+                    }
+                    self.unify(function, expected);
+                    self.applications += 1;
+                    types.push(result);
+                }
+            }
+        }
+        Walk::Done(types.pop().unwrap())
+    }
+
+    fn find(&mut self, mut t: usize) -> usize {
+        let mut path = Vec::new();
+        while let Node::Link(next) = self.nodes[t] {
+            path.push(t);
+            t = next;
+        }
+        for node in path {
+            self.nodes[node] = Node::Link(t);
+        }
+        t
+    }
+
+    // 出現検査をしない単一化。関数型同士も1つにまとめるので、循環した型ができても止まる
+    fn unify(&mut self, left: usize, right: usize) {
+        let mut work = vec![(left, right)];
+        while let Some((left, right)) = work.pop() {
+            let (left, right) = (self.find(left), self.find(right));
+            if left == right {
+                continue;
+            }
+            match (self.nodes[left], self.nodes[right]) {
+                (Node::Var, _) => self.nodes[left] = Node::Link(right),
+                (_, Node::Var) => self.nodes[right] = Node::Link(left),
+                (Node::Arrow(l1, l2), Node::Arrow(r1, r2)) => {
+                    self.nodes[left] = Node::Link(right);
+                    work.push((l2, r2));
+                    work.push((l1, r1));
+                }
+                (Node::Link(_), _) | (_, Node::Link(_)) => unreachable!(),
+            }
+        }
+    }
+
+    // 出現検査をする単一化。失敗したら、引っかかった型変数と型を返す。型が付かないときだけ使う
+    fn unify_checked(&mut self, left: usize, right: usize) -> Result<(), (usize, usize)> {
+        let mut work = vec![(left, right)];
+        while let Some((left, right)) = work.pop() {
+            let (left, right) = (self.find(left), self.find(right));
+            if left == right {
+                continue;
+            }
+            match (self.nodes[left], self.nodes[right]) {
+                (Node::Var, _) => self.bind(left, right)?,
+                (_, Node::Var) => self.bind(right, left)?,
+                // 関数型同士はまとめない。まとめると出現検査の前に循環ができ、報告する型を辿れなくなる
+                (Node::Arrow(l1, l2), Node::Arrow(r1, r2)) => {
+                    work.push((l2, r2));
+                    work.push((l1, r1));
+                }
+                (Node::Link(_), _) | (_, Node::Link(_)) => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    fn bind(&mut self, var: usize, ty: usize) -> Result<(), (usize, usize)> {
+        if self.occurs(var, ty) {
+            return Err((var, ty));
+        }
+        self.nodes[var] = Node::Link(ty);
+        Ok(())
+    }
+
+    fn occurs(&mut self, var: usize, ty: usize) -> bool {
+        let mut visited = HashSet::new();
+        let mut rest = vec![ty];
+        while let Some(t) = rest.pop() {
+            let t = self.find(t);
+            if t == var {
+                return true;
+            }
+            if let Node::Arrow(from, to) = self.nodes[t] {
+                if visited.insert(t) {
+                    rest.push(from);
+                    rest.push(to);
+                }
+            }
+        }
+        false
+    }
+
+    // 全ての節点から深さ優先で辿り、辿っている途中の節点に戻れば循環している
+    fn is_acyclic(&mut self) -> bool {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Open,
+            Closed,
+        }
+        let mut marks = vec![Mark::New; self.nodes.len()];
+        for start in 0..self.nodes.len() {
+            // 節点を閉じる印 (true) を子より先に積む
+            let mut rest = vec![(start, false)];
+            while let Some((t, close)) = rest.pop() {
+                if close {
+                    marks[t] = Mark::Closed;
+                    continue;
+                }
+                let t = self.find(t);
+                match marks[t] {
+                    Mark::Open => return false,
+                    Mark::Closed => continue,
+                    Mark::New => {}
+                }
+                marks[t] = Mark::Open;
+                rest.push((t, true));
+                if let Node::Arrow(from, to) = self.nodes[t] {
+                    rest.push((to, false));
+                    rest.push((from, false));
+                }
+            }
+        }
+        true
+    }
+
+    // 型変数には、左から現れた順に番号を振り直す。循環していない型だけを渡す
+    fn resolve(&mut self, t: usize, names: &mut HashMap<usize, usize>) -> Type {
+        enum Frame {
+            Visit(usize),
+            Arrow,
+        }
+        let mut frames = vec![Frame::Visit(t)];
+        let mut types = Vec::new();
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(t) => {
+                    let t = self.find(t);
+                    match self.nodes[t] {
+                        Node::Arrow(from, to) => {
+                            frames.push(Frame::Arrow);
+                            frames.push(Frame::Visit(to));
+                            frames.push(Frame::Visit(from));
+                        }
+                        _ => {
+                            let next = names.len();
+                            types.push(Type::Var(*names.entry(t).or_insert(next)));
+                        }
+                    }
+                }
+                Frame::Arrow => {
+                    let to = types.pop().unwrap();
+                    let from = types.pop().unwrap();
+                    types.push(from.arrow(to));
+                }
+            }
+        }
+        types.pop().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::Applicative;
+    use std::convert::TryFrom;
+
+    fn ty(s: &str) -> String {
+        infer(&Applicative::try_from(s).unwrap().into())
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn principal_types() {
+        assert_eq!(ty("S"), "(a→b→c)→(a→b)→a→c");
+        assert_eq!(ty("K"), "a→b→a");
+        assert_eq!(ty("I"), "a→a");
+        assert_eq!(ty("SKK"), "a→a");
+        assert_eq!(ty("KI"), "a→b→b");
+        // B = S(KS)K
+        assert_eq!(ty("S(KS)K"), "(a→b)→(c→a)→c→b");
+        assert_eq!(ty("SK"), "(a→b)→a→a");
+    }
+
+    #[test]
+    fn parse() {
+        for s in &["a→a", "(a→b→c)→(a→b)→a→c", "((a→b)→a)→a"] {
+            assert_eq!(Type::try_from(*s).unwrap().to_string(), *s);
+        }
+        assert_eq!(
+            Type::try_from("(p -> q) -> p->q").unwrap().to_string(),
+            "(a→b)→a→b"
+        );
+        assert_eq!(Type::try_from("a→"), Err(NotationError::EmptyTerm(2)));
+        assert_eq!(Type::try_from("(a→b"), Err(NotationError::UnclosedParen(0)));
+        assert_eq!(Type::try_from("a)"), Err(NotationError::UnmatchedParen(1)));
+        assert_eq!(
+            Type::try_from("a→B"),
+            Err(NotationError::UnexpectedChar(2, 'B'))
+        );
+
+        let general = Type::try_from("a→b→a").unwrap();
+        assert!(Type::try_from("(c→c)→d→c→c")
+            .unwrap()
+            .is_instance_of(&general));
+        assert!(!Type::try_from("a→b→b").unwrap().is_instance_of(&general));
+        assert!(!general.is_instance_of(&Type::try_from("a→a→a").unwrap()));
+    }
+
+    #[test]
+    fn occurs_check() {
+        let error = infer(&Ast::try_from("``sii").unwrap()).unwrap_err();
+        assert_eq!(error.subterm(), &Ast::try_from("``sii").unwrap());
+        assert_eq!(
+            error.to_string(),
+            "occurs check: cannot construct the infinite type a = a→b in ``sii"
+        );
+        // 型の付かない部分項を含めば、全体にも型は付かない
+        let error = infer(&Ast::try_from("`k```sii``sii").unwrap()).unwrap_err();
+        assert_eq!(error.subterm(), &Ast::try_from("``sii").unwrap());
+        assert!(!is_typable(&Ast::try_from("``s`sk``sii").unwrap()));
+        assert!(is_typable(&Ast::try_from("``s`ksk").unwrap()));
+    }
+
+    #[test]
+    fn deep_term() {
+        // ``...`ki...i は K I の後に I を並べた項で、型は a→a
+        let n = 100_000;
+        let s = "`".repeat(n) + "k" + &"i".repeat(n);
+        let ast = Ast::try_from(s.as_str()).unwrap();
+        assert_eq!(infer(&ast).unwrap().arity(), 1);
+
+        // `k`k…`ki は深さに比例して型が大きくなる。型は a→b→…→c→c
+        let s = "`k".repeat(n) + "`ki";
+        let ast = Ast::try_from(s.as_str()).unwrap();
+        let ty = infer(&ast).unwrap();
+        assert_eq!(ty.arity(), n + 2);
+        assert!(ty.to_string().ends_with("→t100000→t100001→t100001"));
+        // 型の付かない部分項が深くにあっても、その部分項を報告する
+        let s = "`k".repeat(n) + "``sii";
+        let error = infer(&Ast::try_from(s.as_str()).unwrap()).unwrap_err();
+        assert_eq!(error.subterm(), &Ast::try_from("``sii").unwrap());
+    }
+
+    #[test]
+    fn typable_terms_normalize() {
+        use crate::enumerate;
+        use crate::stack::Stax;
+
+        for leaves in 1..=6 {
+            for ast in enumerate::terms(leaves).filter(is_typable) {
+                let mut stax = Stax::from(Sequence::from(ast.clone()));
+                assert!(stax.eval_with_fuel(10_000).is_ok(), "{:?}", ast);
+            }
+        }
+    }
+}